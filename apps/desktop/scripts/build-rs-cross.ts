@@ -42,7 +42,8 @@ for (const dir of requiredDirs) {
 // Build the Rust binary
 console.log("   Building with cargo...")
 try {
-  execSync("cargo build --release", {
+  // The opus feature lets `record` write .opus files, it needs cmake
+  execSync("cargo build --release --features opus", {
     cwd: rustDir,
     stdio: "inherit",
   })
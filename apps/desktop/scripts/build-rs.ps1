@@ -94,7 +94,7 @@ Write-Host "[BUILD] Building Rust binary for Windows..." -ForegroundColor Green
 Write-Host "   This may take a few minutes..." -ForegroundColor Yellow
 
 try {
-    & $cargoPath build --release --verbose
+    & $cargoPath build --release --features opus --verbose
 
     if ($LASTEXITCODE -eq 0) {
         Write-Host "[OK] Rust binary built successfully!" -ForegroundColor Green
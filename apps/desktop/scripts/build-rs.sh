@@ -4,7 +4,7 @@ mkdir -p resources/bin
 
 cd speakmcp-rs
 
-cargo build -r
+cargo build -r --features opus
 
 # Handle different platforms
 if [[ "$OSTYPE" == "msys" || "$OSTYPE" == "win32" || "$OSTYPE" == "cygwin" ]]; then
@@ -0,0 +1,245 @@
+use std::fs::File;
+use std::io::{self, BufWriter};
+use std::path::Path;
+
+use hound::{SampleFormat, WavSpec, WavWriter};
+
+use super::{AudioFormat, SAMPLE_RATE};
+
+// Where recorded samples go, 16 kHz mono f32 in.
+pub trait Sink: Send {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()>;
+    fn finish(self: Box<Self>) -> io::Result<()>;
+}
+
+pub fn create(path: &Path, format: AudioFormat) -> io::Result<Box<dyn Sink>> {
+    match format {
+        AudioFormat::Wav => Ok(Box::new(WavSink::create(path)?)),
+        #[cfg(feature = "opus")]
+        AudioFormat::Opus => Ok(Box::new(opus::OpusSink::create(path)?)),
+        #[cfg(not(feature = "opus"))]
+        AudioFormat::Opus => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "this build has no Opus support, record WAV or build with --features opus",
+        )),
+    }
+}
+
+fn to_i16(sample: f32) -> i16 {
+    (sample.clamp(-1.0, 1.0) * i16::MAX as f32) as i16
+}
+
+fn hound_error(error: hound::Error) -> io::Error {
+    match error {
+        hound::Error::IoError(error) => error,
+        other => io::Error::other(other),
+    }
+}
+
+// 16 bit PCM, what speech to text services take without conversion
+pub struct WavSink {
+    writer: WavWriter<BufWriter<File>>,
+}
+
+impl WavSink {
+    pub fn create(path: &Path) -> io::Result<Self> {
+        let spec = WavSpec {
+            channels: 1,
+            sample_rate: SAMPLE_RATE,
+            bits_per_sample: 16,
+            sample_format: SampleFormat::Int,
+        };
+        let writer = WavWriter::create(path, spec).map_err(hound_error)?;
+        Ok(WavSink { writer })
+    }
+}
+
+impl Sink for WavSink {
+    fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+        for sample in samples {
+            self.writer
+                .write_sample(to_i16(*sample))
+                .map_err(hound_error)?;
+        }
+        Ok(())
+    }
+
+    fn finish(self: Box<Self>) -> io::Result<()> {
+        self.writer.finalize().map_err(hound_error)
+    }
+}
+
+#[cfg(feature = "opus")]
+mod opus {
+    use std::fs::File;
+    use std::io::{self, BufWriter};
+    use std::path::Path;
+
+    use audiopus::coder::Encoder;
+    use audiopus::{Application, Channels, SampleRate};
+    use ogg::{PacketWriteEndInfo, PacketWriter};
+
+    use super::super::SAMPLE_RATE;
+    use super::Sink;
+
+    // 20 ms frames
+    const FRAME: usize = SAMPLE_RATE as usize / 50;
+    // Granule positions count 48 kHz samples whatever the input rate
+    const GRANULE_SCALE: u64 = 48_000 / SAMPLE_RATE as u64;
+    const SERIAL: u32 = 0x5350_4b4d;
+
+    fn opus_error(error: audiopus::Error) -> io::Error {
+        io::Error::other(error.to_string())
+    }
+
+    // Ogg Opus as in RFC 7845, the container browsers and Whisper read
+    pub struct OpusSink {
+        encoder: Encoder,
+        writer: PacketWriter<BufWriter<File>>,
+        pre_skip: u64,
+        // Samples waiting for a full frame
+        frame: Vec<f32>,
+        samples: u64,
+        // Packets are written one behind so the last one can end the stream
+        pending: Option<Vec<u8>>,
+        packets: u64,
+    }
+
+    impl OpusSink {
+        pub fn create(path: &Path) -> io::Result<Self> {
+            let encoder = Encoder::new(SampleRate::Hz16000, Channels::Mono, Application::Voip)
+                .map_err(opus_error)?;
+            let pre_skip = encoder.lookahead().map_err(opus_error)? as u64 * GRANULE_SCALE;
+            let mut writer = PacketWriter::new(BufWriter::new(File::create(path)?));
+
+            let mut head = b"OpusHead".to_vec();
+            head.push(1);
+            head.push(1);
+            head.extend((pre_skip as u16).to_le_bytes());
+            head.extend(SAMPLE_RATE.to_le_bytes());
+            head.extend(0i16.to_le_bytes());
+            head.push(0);
+            writer.write_packet(head.into_boxed_slice(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+            let vendor = concat!("speakmcp-rs ", env!("CARGO_PKG_VERSION"));
+            let mut tags = b"OpusTags".to_vec();
+            tags.extend((vendor.len() as u32).to_le_bytes());
+            tags.extend(vendor.as_bytes());
+            tags.extend(0u32.to_le_bytes());
+            writer.write_packet(tags.into_boxed_slice(), SERIAL, PacketWriteEndInfo::EndPage, 0)?;
+
+            Ok(OpusSink {
+                encoder,
+                writer,
+                pre_skip,
+                frame: Vec::with_capacity(FRAME),
+                samples: 0,
+                pending: None,
+                packets: 0,
+            })
+        }
+
+        fn encode_frame(&mut self) -> io::Result<()> {
+            let mut packet = vec![0; 4000];
+            let len = self
+                .encoder
+                .encode_float(&self.frame, &mut packet)
+                .map_err(opus_error)?;
+            packet.truncate(len);
+            self.frame.clear();
+            if let Some(previous) = self.pending.replace(packet) {
+                self.packets += 1;
+                let granule = self.packets * FRAME as u64 * GRANULE_SCALE;
+                self.writer.write_packet(
+                    previous.into_boxed_slice(),
+                    SERIAL,
+                    PacketWriteEndInfo::NormalPacket,
+                    granule,
+                )?;
+            }
+            Ok(())
+        }
+    }
+
+    impl Sink for OpusSink {
+        fn write(&mut self, samples: &[f32]) -> io::Result<()> {
+            for sample in samples {
+                self.frame.push(*sample);
+                self.samples += 1;
+                if self.frame.len() == FRAME {
+                    self.encode_frame()?;
+                }
+            }
+            Ok(())
+        }
+
+        fn finish(mut self: Box<Self>) -> io::Result<()> {
+            if !self.frame.is_empty() {
+                self.frame.resize(FRAME, 0.0);
+                self.encode_frame()?;
+            }
+            // The last granule position trims the padding of the last frame
+            let granule = self.pre_skip + self.samples * GRANULE_SCALE;
+            let last = self.pending.take().unwrap_or_default();
+            self.writer
+                .write_packet(last.into_boxed_slice(), SERIAL, PacketWriteEndInfo::EndStream, granule)?;
+            let mut file = self.writer.into_inner();
+            io::Write::flush(&mut file)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_16_khz_mono_pcm() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.wav");
+        let mut sink = create(&path, AudioFormat::Wav).unwrap();
+        sink.write(&[0.0, 0.5, -1.0, 2.0]).unwrap();
+        sink.finish().unwrap();
+
+        let mut reader = hound::WavReader::open(&path).unwrap();
+        assert_eq!(reader.spec().sample_rate, 16_000);
+        assert_eq!(reader.spec().channels, 1);
+        let samples: Vec<i16> = reader.samples().map(Result::unwrap).collect();
+        assert_eq!(samples, vec![0, 16383, -32767, 32767]);
+    }
+
+    #[cfg(feature = "opus")]
+    #[test]
+    fn writes_ogg_opus_that_ends_on_the_last_sample() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("out.opus");
+        let mut sink = create(&path, AudioFormat::Opus).unwrap();
+        // A bit over 5 frames of a 440 Hz tone, so the last one is padded
+        let tone: Vec<f32> = (0..1700)
+            .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / SAMPLE_RATE as f32).sin() * 0.5)
+            .collect();
+        sink.write(&tone).unwrap();
+        sink.finish().unwrap();
+
+        let bytes = std::fs::read(&path).unwrap();
+        assert_eq!(&bytes[..4], b"OggS");
+        let mut reader = ogg::PacketReader::new(File::open(&path).unwrap());
+        let head = reader.read_packet().unwrap().unwrap();
+        assert_eq!(&head.data[..8], b"OpusHead");
+        assert_eq!((head.data[8], head.data[9]), (1, 1));
+        let pre_skip = u16::from_le_bytes([head.data[10], head.data[11]]) as u64;
+        assert_eq!(&head.data[12..16], &SAMPLE_RATE.to_le_bytes());
+        let tags = reader.read_packet().unwrap().unwrap();
+        assert_eq!(&tags.data[..8], b"OpusTags");
+
+        let mut audio = Vec::new();
+        while let Some(packet) = reader.read_packet().unwrap() {
+            audio.push(packet);
+        }
+        assert_eq!(audio.len(), 6);
+        let last = audio.last().unwrap();
+        assert!(last.last_in_stream());
+        // Granule positions count 48 kHz samples
+        assert_eq!(last.absgp_page(), pre_skip + 1700 * 3);
+    }
+}
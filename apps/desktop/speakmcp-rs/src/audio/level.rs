@@ -0,0 +1,125 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+use super::SAMPLE_RATE;
+
+pub const DEFAULT_LEVEL_INTERVAL_MS: u64 = 50;
+// Shorter intervals flood the output faster than any meter redraws
+pub const MIN_LEVEL_INTERVAL_MS: u64 = 10;
+// Samples this close to full scale count as clipping
+const CLIP: f32 = 0.999;
+// What silence reads as, digital zero has no level in dB
+const MIN_DBFS: f32 = -100.0;
+
+pub fn dbfs(amplitude: f32) -> f32 {
+    if amplitude <= 0.0 {
+        return MIN_DBFS;
+    }
+    (20.0 * amplitude.log10()).max(MIN_DBFS)
+}
+
+// Loudness over one interval, for meters and "mic too quiet" warnings.
+// `offset_ms` is where the interval ends in the recording.
+#[derive(Serialize, Debug, Clone, Copy, PartialEq)]
+#[serde(tag = "event_type", rename = "level")]
+pub struct Level {
+    pub offset_ms: u64,
+    pub rms: f32,
+    pub peak: f32,
+    // Of the RMS
+    pub dbfs: f32,
+    pub clipping: bool,
+}
+
+// Sums up the audio into one Level per interval of audio time, so a
+// recording reports at the same pace whatever chunks the input delivers.
+pub struct LevelMeter {
+    interval: u64,
+    samples: u64,
+    count: u64,
+    squares: f64,
+    peak: f32,
+}
+
+impl LevelMeter {
+    pub fn new(interval: Duration) -> Self {
+        LevelMeter {
+            interval: ((interval.as_secs_f64() * SAMPLE_RATE as f64) as u64).max(1),
+            samples: 0,
+            count: 0,
+            squares: 0.0,
+            peak: 0.0,
+        }
+    }
+
+    // Moves past audio without reporting it, the next interval starts after
+    pub fn skip(&mut self, samples: usize) {
+        self.samples += samples as u64;
+        self.count = 0;
+        self.squares = 0.0;
+        self.peak = 0.0;
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<Level> {
+        let mut levels = Vec::new();
+        for sample in samples {
+            self.squares += (*sample as f64).powi(2);
+            self.peak = self.peak.max(sample.abs());
+            self.count += 1;
+            self.samples += 1;
+            if self.count == self.interval {
+                let rms = (self.squares / self.count as f64).sqrt() as f32;
+                levels.push(Level {
+                    offset_ms: self.samples * 1000 / SAMPLE_RATE as u64,
+                    rms,
+                    peak: self.peak,
+                    dbfs: dbfs(rms),
+                    clipping: self.peak >= CLIP,
+                });
+                self.count = 0;
+                self.squares = 0.0;
+                self.peak = 0.0;
+            }
+        }
+        levels
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_once_per_interval() {
+        let mut meter = LevelMeter::new(Duration::from_millis(50));
+        // Silence, then a square wave at half scale, then one clipped sample
+        let mut levels = meter.process(&vec![0.0; 700]);
+        assert!(levels.is_empty());
+        let square: Vec<f32> = (0..900).map(|i| if i % 2 == 0 { 0.5 } else { -0.5 }).collect();
+        levels.extend(meter.process(&square));
+        let mut clipped = vec![0.1; 800];
+        clipped[400] = -1.0;
+        levels.extend(meter.process(&clipped));
+
+        assert_eq!(levels.len(), 3);
+        assert_eq!(levels[0].offset_ms, 50);
+        assert!((levels[0].rms - (0.25f32 * 100.0 / 800.0).sqrt()).abs() < 1e-6);
+        assert!(!levels[0].clipping);
+        assert_eq!((levels[1].offset_ms, levels[1].rms, levels[1].peak), (100, 0.5, 0.5));
+        assert!((levels[1].dbfs + 6.02).abs() < 0.01, "{}", levels[1].dbfs);
+        assert!(levels[2].clipping);
+        assert_eq!(dbfs(0.0), -100.0);
+
+        let json = serde_json::to_value(levels[1]).unwrap();
+        assert_eq!(json["event_type"], "level");
+        assert_eq!(json["clipping"], false);
+
+        // Skipped audio only moves the offsets on
+        meter.process(&vec![0.5; 400]);
+        meter.skip(13_200);
+        let levels = meter.process(&vec![0.25; 800]);
+        assert_eq!(levels.len(), 1);
+        assert_eq!((levels[0].offset_ms, levels[0].peak), (1050, 0.25));
+    }
+}
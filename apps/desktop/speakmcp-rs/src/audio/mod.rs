@@ -0,0 +1,372 @@
+mod encode;
+mod level;
+mod preroll;
+mod resample;
+mod source;
+mod vad;
+
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use serde::{Deserialize, Serialize};
+
+pub use level::{Level, DEFAULT_LEVEL_INTERVAL_MS, MIN_LEVEL_INTERVAL_MS};
+pub use preroll::{Monitor, PreRoll};
+pub use source::device_names;
+pub use vad::{VadEvent, VadOptions};
+
+// What speech to text services expect, everything is converted to this
+pub const SAMPLE_RATE: u32 = 16_000;
+// Enough to cover the default hold delay of hotkeys and then some
+pub const DEFAULT_PREROLL_SECONDS: f64 = 2.0;
+// Half a minute of samples is about 2 MB, more is no pre-roll anymore
+pub const MAX_PREROLL_SECONDS: f64 = 30.0;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AudioFormat {
+    #[default]
+    Wav,
+    // Ogg Opus, needs the opus feature
+    Opus,
+}
+
+impl AudioFormat {
+    // Opus for .opus and .ogg files, WAV otherwise
+    pub fn from_path(path: &Path) -> Self {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("opus" | "ogg") => AudioFormat::Opus,
+            _ => AudioFormat::Wav,
+        }
+    }
+}
+
+impl std::str::FromStr for AudioFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "wav" => Ok(AudioFormat::Wav),
+            "opus" => Ok(AudioFormat::Opus),
+            _ => Err(format!("Invalid audio format: {} (expected wav or opus)", s)),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub enum SourceSpec {
+    // An input device by name, None for the default one
+    Device(Option<String>),
+    // A WAV file standing in for a microphone
+    File(PathBuf),
+    // Silence, for machines without any input device
+    Null,
+    // What a Monitor reads, from the given time on as far as its buffer
+    // goes back
+    PreRoll(PreRoll, Option<SystemTime>),
+}
+
+pub struct RecordOptions {
+    pub source: SourceSpec,
+    pub path: PathBuf,
+    pub format: AudioFormat,
+    pub max_duration: Option<Duration>,
+    // Report speech and, if asked to, end the recording after silence
+    pub vad: Option<VadOptions>,
+    // Report the level once per this much audio
+    pub levels: Option<Duration>,
+    // Deliver file and null sources at the pace of a microphone, off in
+    // tests to get through them as fast as possible
+    pub realtime: bool,
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum EndReason {
+    Stopped,
+    SourceEnded,
+    MaxDuration,
+    // The VAD heard speech followed by enough silence
+    Silence,
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct Summary {
+    pub path: PathBuf,
+    pub format: AudioFormat,
+    pub samples: u64,
+    pub duration_ms: u64,
+    pub reason: EndReason,
+}
+
+pub enum AudioEvent {
+    Speech(VadEvent),
+    Level(Level),
+    // The recording ended without being stopped
+    Ended(Result<Summary, String>),
+}
+
+// A recording running on its own thread until it's stopped or its source
+// runs out.
+pub struct Recording {
+    stop: Arc<AtomicBool>,
+    thread: JoinHandle<Result<Summary, String>>,
+}
+
+impl Recording {
+    pub fn is_finished(&self) -> bool {
+        self.thread.is_finished()
+    }
+
+    // Stops the recording unless it already ended, and finishes the file
+    pub fn stop(self) -> Result<Summary, String> {
+        self.stop.store(true, Ordering::SeqCst);
+        self.thread
+            .join()
+            .unwrap_or_else(|_| Err("The recording thread panicked".to_string()))
+    }
+}
+
+// Opens the source and the output file, then records in the background.
+// `on_event` is called on the recording thread.
+pub fn start<F>(options: RecordOptions, mut on_event: F) -> Result<Recording, String>
+where
+    F: FnMut(AudioEvent) + Send + 'static,
+{
+    let stop = Arc::new(AtomicBool::new(false));
+    let (ready_tx, ready_rx) = mpsc::sync_channel(1);
+    let thread_stop = stop.clone();
+
+    // Device streams can't move between threads on every platform, so the
+    // source is opened on the thread that reads it
+    let thread = std::thread::spawn(move || {
+        let opened = source::open(&options.source, options.realtime).and_then(|source| {
+            let sink = encode::create(&options.path, options.format)
+                .map_err(|e| format!("Can't create {}: {}", options.path.display(), e))?;
+            Ok((source, sink))
+        });
+        let (source, sink) = match opened {
+            Ok(opened) => {
+                let _ = ready_tx.send(Ok(()));
+                opened
+            }
+            Err(e) => {
+                let _ = ready_tx.send(Err(e.clone()));
+                return Err(e);
+            }
+        };
+        let result = record(source, sink, &options, &thread_stop, &mut on_event);
+        if !thread_stop.load(Ordering::SeqCst) {
+            on_event(AudioEvent::Ended(result.clone()));
+        }
+        result
+    });
+
+    match ready_rx.recv() {
+        Ok(Ok(())) => Ok(Recording { stop, thread }),
+        Ok(Err(e)) => Err(e),
+        Err(_) => Err("The recording thread panicked".to_string()),
+    }
+}
+
+fn record(
+    mut source: Box<dyn source::Source>,
+    mut sink: Box<dyn encode::Sink>,
+    options: &RecordOptions,
+    stop: &AtomicBool,
+    on_event: &mut dyn FnMut(AudioEvent),
+) -> Result<Summary, String> {
+    let write_error = |e: std::io::Error| format!("Can't write {}: {}", options.path.display(), e);
+    let limit = options
+        .max_duration
+        .map(|duration| (duration.as_secs_f64() * SAMPLE_RATE as f64) as u64);
+    let mut samples = 0u64;
+    let mut vad = options.vad.map(vad::Vad::new);
+    let mut meter = options.levels.map(level::LevelMeter::new);
+    // A pre-roll source hands out what it kept with its first read. Levels
+    // of that are of the past and would all arrive at once, so they're left
+    // out.
+    let mut past = matches!(options.source, SourceSpec::PreRoll(..));
+
+    let reason = loop {
+        if stop.load(Ordering::SeqCst) {
+            break EndReason::Stopped;
+        }
+        let Some(mut chunk) = source.read()? else {
+            break EndReason::SourceEnded;
+        };
+        if let Some(limit) = limit {
+            chunk.truncate((limit - samples) as usize);
+        }
+        sink.write(&chunk).map_err(write_error)?;
+        samples += chunk.len() as u64;
+        if let Some(meter) = &mut meter {
+            if std::mem::take(&mut past) {
+                meter.skip(chunk.len());
+            } else {
+                for level in meter.process(&chunk) {
+                    on_event(AudioEvent::Level(level));
+                }
+            }
+        }
+        if let Some(vad) = &mut vad {
+            for event in vad.process(&chunk) {
+                on_event(AudioEvent::Speech(event));
+            }
+            if vad.should_stop() {
+                break EndReason::Silence;
+            }
+        }
+        if limit == Some(samples) {
+            break EndReason::MaxDuration;
+        }
+    };
+    if let Some(event) = vad.as_mut().and_then(vad::Vad::finish) {
+        on_event(AudioEvent::Speech(event));
+    }
+    sink.finish().map_err(write_error)?;
+
+    Ok(Summary {
+        path: options.path.clone(),
+        format: options.format,
+        samples,
+        duration_ms: samples * 1000 / SAMPLE_RATE as u64,
+        reason,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_wav(path: &Path, rate: u32, channels: u16, samples: &[i16]) {
+        let spec = hound::WavSpec {
+            channels,
+            sample_rate: rate,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+        let mut writer = hound::WavWriter::create(path, spec).unwrap();
+        for sample in samples {
+            writer.write_sample(*sample).unwrap();
+        }
+        writer.finalize().unwrap();
+    }
+
+    fn read_wav(path: &Path) -> (hound::WavSpec, Vec<i16>) {
+        let mut reader = hound::WavReader::open(path).unwrap();
+        let samples = reader.samples().map(Result::unwrap).collect();
+        (reader.spec(), samples)
+    }
+
+    fn options(source: SourceSpec, path: PathBuf) -> RecordOptions {
+        RecordOptions {
+            source,
+            format: AudioFormat::from_path(&path),
+            path,
+            max_duration: None,
+            vad: None,
+            levels: None,
+            realtime: false,
+        }
+    }
+
+    #[test]
+    fn records_file_and_null_sources_as_16_khz_mono() {
+        let dir = tempfile::tempdir().unwrap();
+
+        // Half a second of stereo at 48 kHz, a constant level on the left
+        let input = dir.path().join("input.wav");
+        let frames: Vec<i16> = (0..24_000).flat_map(|_| [8000, 0]).collect();
+        write_wav(&input, 48_000, 2, &frames);
+        let output = dir.path().join("from-file.wav");
+        let summary = start(options(SourceSpec::File(input), output.clone()), |_| {})
+            .unwrap()
+            .stop_when_finished();
+        assert_eq!(summary.reason, EndReason::SourceEnded);
+        assert!((7_990..=8_000).contains(&summary.samples), "{}", summary.samples);
+        let (spec, samples) = read_wav(&output);
+        assert_eq!((spec.sample_rate, spec.channels), (16_000, 1));
+        assert_eq!(samples.len() as u64, summary.samples);
+        assert!(samples[100..].iter().all(|s| (3999..=4000).contains(s)));
+
+        let output = dir.path().join("null.wav");
+        let mut null = options(SourceSpec::Null, output.clone());
+        null.max_duration = Some(Duration::from_millis(250));
+        let summary = start(null, |_| {}).unwrap().stop_when_finished();
+        assert_eq!(summary.reason, EndReason::MaxDuration);
+        assert_eq!((summary.samples, summary.duration_ms), (4000, 250));
+        assert_eq!(read_wav(&output).1, vec![0; 4000]);
+
+        let missing = options(SourceSpec::File(dir.path().join("missing.wav")), output.clone());
+        assert!(start(missing, |_| {}).is_err());
+        // A broken header can't make the resampler loop forever
+        let zero_rate = dir.path().join("zero-rate.wav");
+        write_wav(&zero_rate, 16_000, 1, &[1000; 100]);
+        let mut bytes = std::fs::read(&zero_rate).unwrap();
+        // The sample rate and byte rate of the fmt chunk
+        bytes[24..32].fill(0);
+        std::fs::write(&zero_rate, bytes).unwrap();
+        let error = start(options(SourceSpec::File(zero_rate), output), |_| {})
+            .err()
+            .unwrap();
+        assert!(error.starts_with("Can't read"), "{}", error);
+    }
+
+    #[test]
+    fn stops_a_realtime_recording_on_request() {
+        let dir = tempfile::tempdir().unwrap();
+        let output = dir.path().join("stopped.wav");
+        let mut null = options(SourceSpec::Null, output.clone());
+        null.realtime = true;
+        let recording = start(null, |event| {
+            assert!(!matches!(event, AudioEvent::Ended(_)), "stopping is not an end")
+        })
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(100));
+        let summary = recording.stop().unwrap();
+        assert_eq!(summary.reason, EndReason::Stopped);
+        assert!((960..=3200).contains(&summary.samples), "{}", summary.samples);
+        assert_eq!(read_wav(&output).1.len() as u64, summary.samples);
+    }
+
+    #[test]
+    fn ends_hands_free_dictation_after_silence() {
+        let dir = tempfile::tempdir().unwrap();
+        let fixture = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures/speech-pause-speech.wav");
+        let mut options = options(SourceSpec::File(fixture), dir.path().join("dictation.wav"));
+        options.vad = Some(VadOptions {
+            stop_after_silence_ms: Some(1000),
+            ..VadOptions::default()
+        });
+
+        let (tx, rx) = mpsc::channel();
+        let recording = start(options, move |event| tx.send(event).unwrap()).unwrap();
+        let mut speech = Vec::new();
+        let summary = loop {
+            match rx.recv().unwrap() {
+                AudioEvent::Speech(event) => speech.push(event),
+                AudioEvent::Level(_) => {}
+                AudioEvent::Ended(result) => break result.unwrap(),
+            }
+        };
+        assert_eq!(recording.stop().unwrap(), summary);
+        // The pause within the dictation doesn't end it, the silence after
+        // the second phrase at 3.1 s does
+        assert_eq!(speech.len(), 4, "{:?}", speech);
+        assert_eq!(summary.reason, EndReason::Silence);
+        assert!(summary.duration_ms.abs_diff(4100) <= 60, "{}", summary.duration_ms);
+    }
+
+    impl Recording {
+        fn stop_when_finished(self) -> Summary {
+            while !self.is_finished() {
+                std::thread::sleep(Duration::from_millis(1));
+            }
+            self.stop().unwrap()
+        }
+    }
+}
@@ -0,0 +1,227 @@
+use std::collections::VecDeque;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
+
+use super::source::{self, Source};
+use super::{SourceSpec, SAMPLE_RATE};
+
+// How long a recording waits for the monitor before checking whether it
+// was stopped
+const TAP_POLL: Duration = Duration::from_millis(100);
+
+struct Ring {
+    samples: VecDeque<f32>,
+    capacity: usize,
+    // When the newest sample was captured
+    latest: SystemTime,
+    // Recordings reading from the monitor
+    taps: Vec<Sender<Vec<f32>>>,
+}
+
+// The last seconds of input kept by a Monitor, for recordings to start in
+// the past with. Cloning shares the buffer.
+#[derive(Clone)]
+pub struct PreRoll {
+    ring: Arc<Mutex<Ring>>,
+}
+
+impl PreRoll {
+    fn new(seconds: f64) -> Self {
+        let capacity = (seconds * SAMPLE_RATE as f64) as usize;
+        PreRoll {
+            ring: Arc::new(Mutex::new(Ring {
+                samples: VecDeque::new(),
+                capacity,
+                latest: SystemTime::now(),
+                taps: Vec::new(),
+            })),
+        }
+    }
+
+    fn push(&self, chunk: Vec<f32>) {
+        let mut ring = self.ring.lock().unwrap();
+        ring.latest = SystemTime::now();
+        ring.samples.extend(&chunk);
+        let excess = ring.samples.len().saturating_sub(ring.capacity);
+        ring.samples.drain(..excess);
+        ring.taps.retain(|tap| tap.send(chunk.clone()).is_ok());
+    }
+
+    // Ends the recordings reading from the monitor
+    fn close(&self) {
+        self.ring.lock().unwrap().taps.clear();
+    }
+
+    // A source with what was captured since `since`, as far as the buffer
+    // goes back, followed by everything captured from now on
+    pub(super) fn tap(&self, since: Option<SystemTime>) -> TapSource {
+        let mut ring = self.ring.lock().unwrap();
+        let back = since
+            .and_then(|since| ring.latest.duration_since(since).ok())
+            .map_or(0, |back| (back.as_secs_f64() * SAMPLE_RATE as f64) as usize)
+            .min(ring.samples.len());
+        let preroll = ring.samples.range(ring.samples.len() - back..).copied().collect();
+        let (tx, rx) = mpsc::channel();
+        ring.taps.push(tx);
+        TapSource {
+            preroll: Some(preroll),
+            rx,
+        }
+    }
+}
+
+pub(super) struct TapSource {
+    preroll: Option<Vec<f32>>,
+    rx: Receiver<Vec<f32>>,
+}
+
+impl Source for TapSource {
+    fn read(&mut self) -> Result<Option<Vec<f32>>, String> {
+        if let Some(preroll) = self.preroll.take() {
+            return Ok(Some(preroll));
+        }
+        match self.rx.recv_timeout(TAP_POLL) {
+            Ok(chunk) => Ok(Some(chunk)),
+            Err(RecvTimeoutError::Timeout) => Ok(Some(Vec::new())),
+            // The monitor stopped
+            Err(RecvTimeoutError::Disconnected) => Ok(None),
+        }
+    }
+}
+
+// Reads the input all the time so recordings can include the moments
+// before they were started, such as the hold delay of a hotkey.
+pub struct Monitor {
+    preroll: PreRoll,
+    stop: Arc<AtomicBool>,
+    thread: Option<JoinHandle<()>>,
+}
+
+impl Monitor {
+    // `on_error` runs on the monitor's thread if the source fails or ends
+    pub fn start<F>(
+        spec: SourceSpec,
+        seconds: f64,
+        realtime: bool,
+        on_error: F,
+    ) -> Result<Monitor, String>
+    where
+        F: FnOnce(String) + Send + 'static,
+    {
+        let preroll = PreRoll::new(seconds);
+        let stop = Arc::new(AtomicBool::new(false));
+        let (ready_tx, ready_rx) = mpsc::sync_channel(1);
+        let (thread_preroll, thread_stop) = (preroll.clone(), stop.clone());
+
+        let thread = std::thread::spawn(move || {
+            let mut source = match source::open(&spec, realtime) {
+                Ok(source) => {
+                    let _ = ready_tx.send(Ok(()));
+                    source
+                }
+                Err(e) => {
+                    let _ = ready_tx.send(Err(e));
+                    return;
+                }
+            };
+            let error = loop {
+                if thread_stop.load(Ordering::SeqCst) {
+                    break None;
+                }
+                match source.read() {
+                    Ok(Some(chunk)) => thread_preroll.push(chunk),
+                    Ok(None) => break Some("The pre-roll input ended".to_string()),
+                    Err(e) => break Some(e),
+                }
+            };
+            thread_preroll.close();
+            if let Some(error) = error {
+                on_error(error);
+            }
+        });
+
+        match ready_rx.recv() {
+            Ok(Ok(())) => Ok(Monitor {
+                preroll,
+                stop,
+                thread: Some(thread),
+            }),
+            Ok(Err(e)) => Err(e),
+            Err(_) => Err("The pre-roll thread panicked".to_string()),
+        }
+    }
+
+    // A source for a recording starting at `since`, see PreRoll::tap
+    pub fn source(&self, since: Option<SystemTime>) -> SourceSpec {
+        SourceSpec::PreRoll(self.preroll.clone(), since)
+    }
+}
+
+impl Drop for Monitor {
+    fn drop(&mut self) {
+        self.stop.store(true, Ordering::SeqCst);
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{start, AudioFormat, RecordOptions};
+    use super::*;
+
+    #[test]
+    fn taps_start_with_what_was_captured_since() {
+        let preroll = PreRoll::new(0.5);
+        for i in 0..50 {
+            preroll.push(vec![i as f32; 320]);
+        }
+        let latest = preroll.ring.lock().unwrap().latest;
+
+        // Only the last half second is kept
+        let mut everything = preroll.tap(Some(latest - Duration::from_secs(5)));
+        let kept = everything.read().unwrap().unwrap();
+        assert_eq!(kept.len(), 8000);
+        assert_eq!((kept[0], kept[7999]), (25.0, 49.0));
+
+        let mut recent = preroll.tap(Some(latest - Duration::from_millis(40)));
+        assert_eq!(recent.read().unwrap().unwrap(), [vec![48.0; 320], vec![49.0; 320]].concat());
+        let mut none = preroll.tap(None);
+        assert!(none.read().unwrap().unwrap().is_empty());
+
+        // Then what comes after, until the monitor closes
+        preroll.push(vec![50.0; 320]);
+        for tap in [&mut everything, &mut recent, &mut none] {
+            assert_eq!(tap.read().unwrap().unwrap(), vec![50.0; 320]);
+        }
+        preroll.close();
+        assert_eq!(recent.read().unwrap(), None);
+    }
+
+    #[test]
+    fn recordings_from_the_monitor_reach_back() {
+        let dir = tempfile::tempdir().unwrap();
+        let monitor = Monitor::start(SourceSpec::Null, 1.0, true, |e| panic!("{}", e)).unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+
+        // Like a hold confirmed 300 ms after the key went down
+        let pressed = SystemTime::now() - Duration::from_millis(300);
+        let options = RecordOptions {
+            source: monitor.source(Some(pressed)),
+            path: dir.path().join("held.wav"),
+            format: AudioFormat::Wav,
+            max_duration: None,
+            vad: None,
+            levels: None,
+            realtime: true,
+        };
+        let recording = start(options, |_| {}).unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let summary = recording.stop().unwrap();
+        assert!((400..=700).contains(&summary.duration_ms), "{}", summary.duration_ms);
+    }
+}
@@ -0,0 +1,86 @@
+use std::collections::VecDeque;
+
+// Streaming conversion of mono audio to another sample rate. Speech only
+// needs what's below 8 kHz, so a box filter against aliasing followed by
+// linear interpolation is plenty.
+pub struct Resampler {
+    // Input samples per output sample
+    step: f64,
+    // Position of the next output sample in `filtered`
+    position: f64,
+    filtered: Vec<f32>,
+    window: VecDeque<f32>,
+    window_len: usize,
+    sum: f32,
+}
+
+impl Resampler {
+    pub fn new(from: u32, to: u32) -> Self {
+        assert!(from > 0 && to > 0, "can't resample from {} Hz to {} Hz", from, to);
+        let step = from as f64 / to as f64;
+        Resampler {
+            step,
+            position: 0.0,
+            filtered: Vec::new(),
+            window: VecDeque::new(),
+            window_len: step.round().max(1.0) as usize,
+            sum: 0.0,
+        }
+    }
+
+    pub fn process(&mut self, input: &[f32]) -> Vec<f32> {
+        if self.step == 1.0 {
+            return input.to_vec();
+        }
+        for sample in input {
+            self.window.push_back(*sample);
+            self.sum += sample;
+            if self.window.len() > self.window_len {
+                self.sum -= self.window.pop_front().unwrap();
+            }
+            self.filtered.push(self.sum / self.window.len() as f32);
+        }
+
+        let mut output = Vec::new();
+        while self.position + 1.0 < self.filtered.len() as f64 {
+            let index = self.position as usize;
+            let fraction = (self.position - index as f64) as f32;
+            let (a, b) = (self.filtered[index], self.filtered[index + 1]);
+            output.push(a + (b - a) * fraction);
+            self.position += self.step;
+        }
+        let consumed = (self.position as usize).min(self.filtered.len());
+        self.filtered.drain(..consumed);
+        self.position -= consumed as f64;
+        output
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keeps_duration_and_pitch_across_chunks() {
+        // One second of a 440 Hz tone at 44.1 kHz, fed in odd sized chunks
+        let input: Vec<f32> = (0..44_100)
+            .map(|i| (i as f32 * 440.0 * std::f32::consts::TAU / 44_100.0).sin())
+            .collect();
+        let mut resampler = Resampler::new(44_100, 16_000);
+        let output: Vec<f32> = input
+            .chunks(441)
+            .flat_map(|chunk| resampler.process(chunk))
+            .collect();
+        assert!((15_990..=16_000).contains(&output.len()), "{}", output.len());
+
+        // 440 rising zero crossings, give or take the edges
+        let crossings = output
+            .windows(2)
+            .filter(|pair| pair[0] < 0.0 && pair[1] >= 0.0)
+            .count();
+        assert!((439..=441).contains(&crossings), "{}", crossings);
+
+        let mut same = Resampler::new(16_000, 16_000);
+        assert_eq!(same.process(&[0.5, -0.5]), vec![0.5, -0.5]);
+    }
+}
@@ -0,0 +1,208 @@
+use std::path::Path;
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::time::{Duration, Instant};
+
+use cpal::traits::{DeviceTrait, HostTrait, StreamTrait};
+use cpal::{FromSample, Sample, SampleFormat, SizedSample};
+
+use super::resample::Resampler;
+use super::{SourceSpec, SAMPLE_RATE};
+
+// Samples handed out per read by the file and null sources, 20 ms
+const CHUNK: usize = SAMPLE_RATE as usize / 50;
+// How long a device read waits before giving the recorder a chance to stop
+const DEVICE_POLL: Duration = Duration::from_millis(100);
+
+// Where recorded samples come from, always 16 kHz mono f32.
+pub trait Source {
+    // The next samples, possibly none yet, or None once the source ended
+    fn read(&mut self) -> Result<Option<Vec<f32>>, String>;
+}
+
+pub fn open(spec: &SourceSpec, realtime: bool) -> Result<Box<dyn Source>, String> {
+    let pacer = realtime.then(Pacer::default);
+    Ok(match spec {
+        SourceSpec::Device(name) => Box::new(DeviceSource::open(name.as_deref())?),
+        SourceSpec::File(path) => Box::new(FileSource::open(path, pacer)?),
+        SourceSpec::Null => Box::new(NullSource { pacer }),
+        SourceSpec::PreRoll(preroll, since) => Box::new(preroll.tap(*since)),
+    })
+}
+
+pub fn device_names() -> Result<Vec<String>, String> {
+    let devices = cpal::default_host()
+        .input_devices()
+        .map_err(|e| e.to_string())?;
+    Ok(devices.filter_map(|device| device.name().ok()).collect())
+}
+
+fn downmix(data: &[f32], channels: usize) -> Vec<f32> {
+    data.chunks(channels.max(1))
+        .map(|frame| frame.iter().sum::<f32>() / frame.len() as f32)
+        .collect()
+}
+
+// Hands out samples no faster than they would come from a microphone
+#[derive(Default)]
+struct Pacer {
+    started: Option<Instant>,
+    samples: u64,
+}
+
+impl Pacer {
+    // Waits until `samples` more would have been captured
+    fn wait(&mut self, samples: usize) {
+        let started = *self.started.get_or_insert_with(Instant::now);
+        self.samples += samples as u64;
+        let due = started + Duration::from_secs_f64(self.samples as f64 / SAMPLE_RATE as f64);
+        if let Some(wait) = due.checked_duration_since(Instant::now()) {
+            std::thread::sleep(wait);
+        }
+    }
+}
+
+struct DeviceSource {
+    // Dropping the stream stops the capture
+    _stream: cpal::Stream,
+    data: Receiver<Result<Vec<f32>, String>>,
+    resampler: Resampler,
+}
+
+impl DeviceSource {
+    fn open(name: Option<&str>) -> Result<Self, String> {
+        let host = cpal::default_host();
+        let device = match name {
+            Some(name) => host
+                .input_devices()
+                .map_err(|e| e.to_string())?
+                .find(|device| device.name().is_ok_and(|n| n == name))
+                .ok_or_else(|| format!("No input device named {:?}", name))?,
+            None => host
+                .default_input_device()
+                .ok_or("No default input device")?,
+        };
+        let config = device
+            .default_input_config()
+            .map_err(|e| e.to_string())?;
+
+        let (tx, data) = mpsc::channel();
+        let stream = match config.sample_format() {
+            SampleFormat::F32 => build::<f32>(&device, &config.config(), tx),
+            SampleFormat::I16 => build::<i16>(&device, &config.config(), tx),
+            SampleFormat::U16 => build::<u16>(&device, &config.config(), tx),
+            SampleFormat::I32 => build::<i32>(&device, &config.config(), tx),
+            SampleFormat::U8 => build::<u8>(&device, &config.config(), tx),
+            other => return Err(format!("Unsupported sample format {}", other)),
+        }?;
+        stream.play().map_err(|e| e.to_string())?;
+
+        Ok(DeviceSource {
+            _stream: stream,
+            data,
+            resampler: Resampler::new(config.sample_rate().0, SAMPLE_RATE),
+        })
+    }
+}
+
+fn build<T>(
+    device: &cpal::Device,
+    config: &cpal::StreamConfig,
+    tx: mpsc::Sender<Result<Vec<f32>, String>>,
+) -> Result<cpal::Stream, String>
+where
+    T: SizedSample,
+    f32: FromSample<T>,
+{
+    let channels = config.channels as usize;
+    let errors = tx.clone();
+    device
+        .build_input_stream(
+            config,
+            move |data: &[T], _: &cpal::InputCallbackInfo| {
+                let samples: Vec<f32> = data.iter().map(|s| f32::from_sample(*s)).collect();
+                let _ = tx.send(Ok(downmix(&samples, channels)));
+            },
+            move |error| {
+                let _ = errors.send(Err(error.to_string()));
+            },
+            None,
+        )
+        .map_err(|e| e.to_string())
+}
+
+impl Source for DeviceSource {
+    fn read(&mut self) -> Result<Option<Vec<f32>>, String> {
+        match self.data.recv_timeout(DEVICE_POLL) {
+            Ok(data) => Ok(Some(self.resampler.process(&data?))),
+            Err(RecvTimeoutError::Timeout) => Ok(Some(Vec::new())),
+            Err(RecvTimeoutError::Disconnected) => Err("The input device went away".to_string()),
+        }
+    }
+}
+
+// A WAV file of any rate and channel count, played like a microphone would
+// deliver it
+struct FileSource {
+    samples: Vec<f32>,
+    position: usize,
+    pacer: Option<Pacer>,
+}
+
+impl FileSource {
+    fn open(path: &Path, pacer: Option<Pacer>) -> Result<Self, String> {
+        let mut reader = hound::WavReader::open(path)
+            .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+        let spec = reader.spec();
+        if spec.sample_rate == 0 {
+            return Err(format!("Can't read {}: the sample rate is 0 Hz", path.display()));
+        }
+        let samples: Vec<f32> = match spec.sample_format {
+            hound::SampleFormat::Float => reader.samples::<f32>().collect::<Result<_, _>>(),
+            hound::SampleFormat::Int => {
+                let scale = (1i64 << (spec.bits_per_sample - 1)) as f32;
+                reader
+                    .samples::<i32>()
+                    .map(|sample| sample.map(|s| s as f32 / scale))
+                    .collect::<Result<_, _>>()
+            }
+        }
+        .map_err(|e| format!("Can't read {}: {}", path.display(), e))?;
+
+        let mono = downmix(&samples, spec.channels as usize);
+        let mut resampler = Resampler::new(spec.sample_rate, SAMPLE_RATE);
+        Ok(FileSource {
+            samples: resampler.process(&mono),
+            position: 0,
+            pacer,
+        })
+    }
+}
+
+impl Source for FileSource {
+    fn read(&mut self) -> Result<Option<Vec<f32>>, String> {
+        if self.position >= self.samples.len() {
+            return Ok(None);
+        }
+        let end = (self.position + CHUNK).min(self.samples.len());
+        let chunk = self.samples[self.position..end].to_vec();
+        self.position = end;
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(chunk.len());
+        }
+        Ok(Some(chunk))
+    }
+}
+
+// Endless silence
+struct NullSource {
+    pacer: Option<Pacer>,
+}
+
+impl Source for NullSource {
+    fn read(&mut self) -> Result<Option<Vec<f32>>, String> {
+        if let Some(pacer) = &mut self.pacer {
+            pacer.wait(CHUNK);
+        }
+        Ok(Some(vec![0.0; CHUNK]))
+    }
+}
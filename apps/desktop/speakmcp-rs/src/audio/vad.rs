@@ -0,0 +1,308 @@
+use serde::{Deserialize, Serialize};
+
+use super::level::dbfs;
+use super::SAMPLE_RATE;
+
+// Frames the decision is made on, 20 ms
+const FRAME: usize = SAMPLE_RATE as usize / 50;
+const FRAME_MS: u64 = 20;
+// How fast the noise floor follows louder background noise, per frame
+const FLOOR_RISE: f32 = 0.05;
+// Speech has quieter frames between words well within this, anything
+// louder for longer is background noise that got louder
+const LONGEST_VOICED_RUN_MS: u64 = 3000;
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+#[serde(default)]
+pub struct VadOptions {
+    // Frames quieter than this are never speech, the visualizer's
+    // MIN_DECIBELS
+    pub threshold_db: f32,
+    // How far above the background noise speech has to be
+    pub margin_db: f32,
+    // Louder frames have to last this long to start speech, which keeps
+    // clicks and bumps out
+    pub min_speech_ms: u64,
+    // Pauses shorter than this stay within speech
+    pub hangover_ms: u64,
+    // End the recording after this much silence following speech
+    pub stop_after_silence_ms: Option<u64>,
+}
+
+impl Default for VadOptions {
+    fn default() -> Self {
+        VadOptions {
+            threshold_db: -45.0,
+            margin_db: 10.0,
+            min_speech_ms: 100,
+            hangover_ms: 400,
+            stop_after_silence_ms: None,
+        }
+    }
+}
+
+// Offsets are from the start of the recording
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(tag = "event_type", rename_all = "snake_case")]
+pub enum VadEvent {
+    SpeechStart { offset_ms: u64 },
+    SpeechEnd { offset_ms: u64 },
+}
+
+// On the same scale as level events
+fn frame_db(samples: &[f32]) -> f32 {
+    let power = samples.iter().map(|s| s * s).sum::<f32>() / samples.len().max(1) as f32;
+    dbfs(power.sqrt())
+}
+
+// Energy based voice activity detection. A frame is speech when it's
+// louder than the threshold and well above the noise floor, which is
+// tracked over the frames that aren't speech and over runs of voiced
+// frames too long to be speech.
+pub struct Vad {
+    options: VadOptions,
+    pending: Vec<f32>,
+    frames: u64,
+    floor: Option<f32>,
+    speaking: bool,
+    // Consecutive speech frames while silent, silent frames while speaking
+    run: u64,
+    // Consecutive voiced frames, whether or not they started speech
+    voiced_run: u64,
+    // The frame after the last voiced frame
+    voiced_until: Option<u64>,
+    // The frame after the last voiced frame of confirmed speech, a lone
+    // click doesn't count
+    speech_until: Option<u64>,
+}
+
+impl Vad {
+    pub fn new(options: VadOptions) -> Self {
+        Vad {
+            options,
+            pending: Vec::with_capacity(FRAME),
+            frames: 0,
+            floor: None,
+            speaking: false,
+            run: 0,
+            voiced_run: 0,
+            voiced_until: None,
+            speech_until: None,
+        }
+    }
+
+    pub fn process(&mut self, samples: &[f32]) -> Vec<VadEvent> {
+        let mut events = Vec::new();
+        for sample in samples {
+            self.pending.push(*sample);
+            if self.pending.len() == FRAME {
+                let db = frame_db(&self.pending);
+                self.pending.clear();
+                events.extend(self.frame(db));
+            }
+        }
+        events
+    }
+
+    fn frame(&mut self, db: f32) -> Option<VadEvent> {
+        // Recordings start before the speaker does, so the first frame
+        // is taken as background
+        let floor = self.floor.get_or_insert(db);
+        let voiced = db > self.options.threshold_db && db > *floor + self.options.margin_db;
+        self.voiced_run = if voiced { self.voiced_run + 1 } else { 0 };
+        if db < *floor {
+            *floor = db;
+        } else if !voiced || self.voiced_run * FRAME_MS > LONGEST_VOICED_RUN_MS {
+            *floor += (db - *floor) * FLOOR_RISE;
+        }
+        self.frames += 1;
+
+        if voiced {
+            self.voiced_until = Some(self.frames);
+        }
+        if self.speaking && voiced {
+            self.speech_until = self.voiced_until;
+        }
+        if voiced != self.speaking {
+            self.run += 1;
+        } else {
+            self.run = 0;
+        }
+
+        if self.run == 0 {
+            return None;
+        }
+        if !self.speaking && self.run * FRAME_MS >= self.options.min_speech_ms {
+            let start = self.frames - self.run;
+            self.speaking = true;
+            self.speech_until = self.voiced_until;
+            self.run = 0;
+            return Some(VadEvent::SpeechStart {
+                offset_ms: start * FRAME_MS,
+            });
+        }
+        if self.speaking && self.run * FRAME_MS >= self.options.hangover_ms {
+            return self.end();
+        }
+        None
+    }
+
+    fn end(&mut self) -> Option<VadEvent> {
+        if !self.speaking {
+            return None;
+        }
+        self.speaking = false;
+        self.run = 0;
+        Some(VadEvent::SpeechEnd {
+            offset_ms: self.voiced_until.unwrap_or(self.frames) * FRAME_MS,
+        })
+    }
+
+    // Ends speech that is still going when the recording stops
+    pub fn finish(&mut self) -> Option<VadEvent> {
+        self.end()
+    }
+
+    // Whether speech was heard and has been followed by enough silence
+    pub fn should_stop(&self) -> bool {
+        match (self.options.stop_after_silence_ms, self.speech_until) {
+            (Some(ms), Some(speech_until)) => {
+                !self.speaking && (self.frames - speech_until) * FRAME_MS >= ms
+            }
+            _ => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    fn fixture(name: &str) -> Vec<f32> {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("fixtures").join(name);
+        let mut reader = hound::WavReader::open(path).unwrap();
+        assert_eq!(reader.spec().sample_rate, SAMPLE_RATE);
+        reader
+            .samples::<i16>()
+            .map(|sample| sample.unwrap() as f32 / i16::MAX as f32)
+            .collect()
+    }
+
+    fn detect(samples: &[f32], options: VadOptions) -> Vec<VadEvent> {
+        let mut vad = Vad::new(options);
+        // In chunks that don't line up with frames, like a device delivers
+        let mut events: Vec<VadEvent> = samples
+            .chunks(441)
+            .flat_map(|chunk| vad.process(chunk))
+            .collect();
+        events.extend(vad.finish());
+        events
+    }
+
+    fn near(event: VadEvent, expected_ms: u64) -> bool {
+        let (VadEvent::SpeechStart { offset_ms } | VadEvent::SpeechEnd { offset_ms }) = event;
+        offset_ms.abs_diff(expected_ms) <= 60
+    }
+
+    #[test]
+    fn finds_speech_in_the_fixtures() {
+        // Quiet room, speech from 0.5 to 1.7 s and 2.3 to 3.1 s
+        let speech = fixture("speech-pause-speech.wav");
+        let events = detect(&speech, VadOptions::default());
+        assert_eq!(events.len(), 4, "{:?}", events);
+        assert!(matches!(events[0], VadEvent::SpeechStart { .. }) && near(events[0], 500));
+        assert!(matches!(events[1], VadEvent::SpeechEnd { .. }) && near(events[1], 1700));
+        assert!(near(events[2], 2300) && near(events[3], 3100), "{:?}", events);
+
+        // A longer hangover bridges the pause
+        let events = detect(
+            &speech,
+            VadOptions {
+                hangover_ms: 800,
+                ..VadOptions::default()
+            },
+        );
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(near(events[0], 500) && near(events[1], 3100));
+
+        // A fan well above the threshold is background, not speech
+        assert_eq!(detect(&fixture("fan-noise.wav"), VadOptions::default()), vec![]);
+
+        // Speech over the fan still stands out
+        let events = detect(&fixture("speech-over-fan.wav"), VadOptions::default());
+        assert_eq!(events.len(), 2, "{:?}", events);
+        assert!(near(events[0], 800) && near(events[1], 2000), "{:?}", events);
+    }
+
+    #[test]
+    fn asks_to_stop_after_silence_following_speech() {
+        let mut vad = Vad::new(VadOptions {
+            stop_after_silence_ms: Some(1000),
+            ..VadOptions::default()
+        });
+        let silence = vec![0.0001; FRAME];
+        let speech: Vec<f32> = (0..FRAME).map(|i| if i % 2 == 0 { 0.3 } else { -0.3 }).collect();
+
+        // Silence alone never stops hands-free dictation
+        for _ in 0..100 {
+            vad.process(&silence);
+        }
+        assert!(!vad.should_stop());
+        for _ in 0..10 {
+            vad.process(&speech);
+        }
+        for _ in 0..49 {
+            vad.process(&silence);
+            assert!(!vad.should_stop());
+        }
+        vad.process(&silence);
+        assert!(vad.should_stop());
+    }
+
+    fn square(amplitude: f32) -> Vec<f32> {
+        (0..FRAME)
+            .map(|i| if i % 2 == 0 { amplitude } else { -amplitude })
+            .collect()
+    }
+
+    #[test]
+    fn a_click_is_no_speech_to_stop_after() {
+        let mut vad = Vad::new(VadOptions {
+            stop_after_silence_ms: Some(1000),
+            ..VadOptions::default()
+        });
+        // The hotkey press picked up by the mic, a single loud frame
+        let mut events = vad.process(&square(0.0001));
+        events.extend(vad.process(&square(0.3)));
+        for _ in 0..200 {
+            events.extend(vad.process(&square(0.0001)));
+            assert!(!vad.should_stop());
+        }
+        assert_eq!(events, vec![]);
+    }
+
+    #[test]
+    fn follows_background_noise_that_got_louder() {
+        let mut vad = Vad::new(VadOptions {
+            stop_after_silence_ms: Some(1000),
+            ..VadOptions::default()
+        });
+        // A quiet room at -60 dBFS, then a fan at -35 dBFS starts
+        let mut events = Vec::new();
+        for _ in 0..50 {
+            events.extend(vad.process(&square(0.001)));
+        }
+        let mut frames = 0;
+        while !vad.should_stop() {
+            events.extend(vad.process(&square(0.0178)));
+            frames += 1;
+            assert!(frames < 500, "still speech after 10 s of fan: {:?}", events);
+        }
+        // Taken for speech at first, then learned as background
+        assert!(matches!(
+            events[..],
+            [VadEvent::SpeechStart { offset_ms: 1000 }, VadEvent::SpeechEnd { .. }]
+        ), "{:?}", events);
+    }
+}
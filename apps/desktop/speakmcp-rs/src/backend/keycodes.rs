@@ -0,0 +1,170 @@
+use enigo::Key;
+
+// Linux input event codes (linux/input-event-codes.h)
+pub const KEY_ESC: u16 = 1;
+pub const KEY_BACKSPACE: u16 = 14;
+pub const KEY_TAB: u16 = 15;
+pub const KEY_ENTER: u16 = 28;
+pub const KEY_LEFTCTRL: u16 = 29;
+pub const KEY_LEFTSHIFT: u16 = 42;
+pub const KEY_RIGHTSHIFT: u16 = 54;
+pub const KEY_LEFTALT: u16 = 56;
+pub const KEY_SPACE: u16 = 57;
+pub const KEY_CAPSLOCK: u16 = 58;
+pub const KEY_RIGHTCTRL: u16 = 97;
+pub const KEY_HOME: u16 = 102;
+pub const KEY_UP: u16 = 103;
+pub const KEY_PAGEUP: u16 = 104;
+pub const KEY_LEFT: u16 = 105;
+pub const KEY_RIGHT: u16 = 106;
+pub const KEY_END: u16 = 107;
+pub const KEY_DOWN: u16 = 108;
+pub const KEY_PAGEDOWN: u16 = 109;
+pub const KEY_INSERT: u16 = 110;
+pub const KEY_DELETE: u16 = 111;
+pub const KEY_LEFTMETA: u16 = 125;
+
+const F1_TO_F10: u16 = 59;
+const F11_TO_F12: u16 = 87;
+const F13_TO_F20: u16 = 183;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Modifier {
+    Shift,
+    Control,
+    Alt,
+    Meta,
+}
+
+// Evdev code, xkb keysym name and modifier of the named keys both virtual
+// keyboards can send.
+pub fn named_key(key: Key) -> Option<(u16, &'static str, Option<Modifier>)> {
+    let named = match key {
+        Key::Escape => (KEY_ESC, "Escape", None),
+        Key::Backspace => (KEY_BACKSPACE, "BackSpace", None),
+        Key::Tab => (KEY_TAB, "Tab", None),
+        Key::Return => (KEY_ENTER, "Return", None),
+        Key::Space => (KEY_SPACE, "space", None),
+        Key::CapsLock => (KEY_CAPSLOCK, "Caps_Lock", None),
+        Key::Home => (KEY_HOME, "Home", None),
+        Key::End => (KEY_END, "End", None),
+        Key::PageUp => (KEY_PAGEUP, "Prior", None),
+        Key::PageDown => (KEY_PAGEDOWN, "Next", None),
+        Key::UpArrow => (KEY_UP, "Up", None),
+        Key::DownArrow => (KEY_DOWN, "Down", None),
+        Key::LeftArrow => (KEY_LEFT, "Left", None),
+        Key::RightArrow => (KEY_RIGHT, "Right", None),
+        Key::Insert => (KEY_INSERT, "Insert", None),
+        Key::Delete => (KEY_DELETE, "Delete", None),
+        Key::Shift | Key::LShift => (KEY_LEFTSHIFT, "Shift_L", Some(Modifier::Shift)),
+        Key::RShift => (KEY_RIGHTSHIFT, "Shift_R", Some(Modifier::Shift)),
+        Key::Control | Key::LControl => (KEY_LEFTCTRL, "Control_L", Some(Modifier::Control)),
+        Key::RControl => (KEY_RIGHTCTRL, "Control_R", Some(Modifier::Control)),
+        Key::Alt | Key::Option => (KEY_LEFTALT, "Alt_L", Some(Modifier::Alt)),
+        Key::Meta => (KEY_LEFTMETA, "Super_L", Some(Modifier::Meta)),
+        Key::F1 => (F1_TO_F10, "F1", None),
+        Key::F2 => (F1_TO_F10 + 1, "F2", None),
+        Key::F3 => (F1_TO_F10 + 2, "F3", None),
+        Key::F4 => (F1_TO_F10 + 3, "F4", None),
+        Key::F5 => (F1_TO_F10 + 4, "F5", None),
+        Key::F6 => (F1_TO_F10 + 5, "F6", None),
+        Key::F7 => (F1_TO_F10 + 6, "F7", None),
+        Key::F8 => (F1_TO_F10 + 7, "F8", None),
+        Key::F9 => (F1_TO_F10 + 8, "F9", None),
+        Key::F10 => (F1_TO_F10 + 9, "F10", None),
+        Key::F11 => (F11_TO_F12, "F11", None),
+        Key::F12 => (F11_TO_F12 + 1, "F12", None),
+        Key::F13 => (F13_TO_F20, "F13", None),
+        Key::F14 => (F13_TO_F20 + 1, "F14", None),
+        Key::F15 => (F13_TO_F20 + 2, "F15", None),
+        Key::F16 => (F13_TO_F20 + 3, "F16", None),
+        Key::F17 => (F13_TO_F20 + 4, "F17", None),
+        Key::F18 => (F13_TO_F20 + 5, "F18", None),
+        Key::F19 => (F13_TO_F20 + 6, "F19", None),
+        Key::F20 => (F13_TO_F20 + 7, "F20", None),
+        Key::Unicode('\n') => (KEY_ENTER, "Return", None),
+        Key::Unicode('\t') => (KEY_TAB, "Tab", None),
+        Key::Unicode(' ') => (KEY_SPACE, "space", None),
+        _ => return None,
+    };
+    Some(named)
+}
+
+// Every named key once, for building keymaps and uinput capabilities
+pub const NAMED_KEYS: &[Key] = &[
+    Key::Escape,
+    Key::Backspace,
+    Key::Tab,
+    Key::Return,
+    Key::Space,
+    Key::CapsLock,
+    Key::Home,
+    Key::End,
+    Key::PageUp,
+    Key::PageDown,
+    Key::UpArrow,
+    Key::DownArrow,
+    Key::LeftArrow,
+    Key::RightArrow,
+    Key::Insert,
+    Key::Delete,
+    Key::LShift,
+    Key::RShift,
+    Key::LControl,
+    Key::RControl,
+    Key::Alt,
+    Key::Meta,
+    Key::F1,
+    Key::F2,
+    Key::F3,
+    Key::F4,
+    Key::F5,
+    Key::F6,
+    Key::F7,
+    Key::F8,
+    Key::F9,
+    Key::F10,
+    Key::F11,
+    Key::F12,
+    Key::F13,
+    Key::F14,
+    Key::F15,
+    Key::F16,
+    Key::F17,
+    Key::F18,
+    Key::F19,
+    Key::F20,
+];
+
+// Key code and whether Shift is needed to type `c` on a US QWERTY layout
+pub fn us_layout(c: char) -> Option<(u16, bool)> {
+    const ROWS: &[(&str, &str, u16)] = &[
+        ("1234567890-=", "!@#$%^&*()_+", 2),
+        ("qwertyuiop[]", "QWERTYUIOP{}", 16),
+        ("asdfghjkl;'`", "ASDFGHJKL:\"~", 30),
+        ("\\zxcvbnm,./", "|ZXCVBNM<>?", 43),
+    ];
+    for (plain, shifted, first) in ROWS {
+        if let Some(i) = plain.chars().position(|p| p == c) {
+            return Some((first + i as u16, false));
+        }
+        if let Some(i) = shifted.chars().position(|s| s == c) {
+            return Some((first + i as u16, true));
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn maps_characters_to_us_keys() {
+        assert_eq!(us_layout('a'), Some((30, false)));
+        assert_eq!(us_layout('Q'), Some((16, true)));
+        assert_eq!(us_layout('?'), Some((53, true)));
+        assert_eq!(us_layout('0'), Some((11, false)));
+        assert_eq!(us_layout('é'), None);
+    }
+}
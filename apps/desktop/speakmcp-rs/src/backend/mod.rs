@@ -0,0 +1,217 @@
+use std::fmt;
+use std::str::FromStr;
+
+use enigo::{Direction, Enigo, InputResult, Key, Keyboard, Settings};
+use serde::Deserialize;
+
+#[cfg(target_os = "linux")]
+mod keycodes;
+#[cfg(target_os = "linux")]
+mod uinput;
+#[cfg(target_os = "linux")]
+mod wayland;
+
+// Something that can inject keystrokes into the session. Writing, key
+// sequences, pasting and undo all go through this.
+pub trait Backend: Keyboard {
+    fn name(&self) -> &'static str;
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum BackendKind {
+    // XTest through enigo, the only choice on macOS and Windows
+    X11,
+    // A virtual keyboard device created through /dev/uinput
+    Uinput,
+    // The zwp_virtual_keyboard_v1 protocol
+    Wayland,
+}
+
+impl BackendKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            BackendKind::X11 => "x11",
+            BackendKind::Uinput => "uinput",
+            BackendKind::Wayland => "wayland",
+        }
+    }
+}
+
+impl FromStr for BackendKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "x11" => Ok(BackendKind::X11),
+            "uinput" => Ok(BackendKind::Uinput),
+            "wayland" => Ok(BackendKind::Wayland),
+            _ => Err(format!(
+                "Unknown backend: {} (expected x11, uinput or wayland)",
+                s
+            )),
+        }
+    }
+}
+
+#[derive(Debug)]
+pub struct BackendError {
+    // "input" or "listener"
+    pub role: &'static str,
+    // Every backend that was tried with the reason it failed
+    pub attempts: Vec<(&'static str, String)>,
+}
+
+impl fmt::Display for BackendError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self.attempts.as_slice() {
+            [(name, reason)] => write!(
+                f,
+                "The {} {} backend failed: {}",
+                name, self.role, reason
+            ),
+            attempts => {
+                write!(f, "No {} backend works", self.role)?;
+                for (i, (name, reason)) in attempts.iter().enumerate() {
+                    let separator = if i == 0 { ":" } else { ";" };
+                    write!(f, "{} {}: {}", separator, name, reason)?;
+                }
+                Ok(())
+            }
+        }
+    }
+}
+
+impl std::error::Error for BackendError {}
+
+struct EnigoBackend(Enigo);
+
+impl Keyboard for EnigoBackend {
+    fn fast_text(&mut self, text: &str) -> InputResult<Option<()>> {
+        self.0.fast_text(text)
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> InputResult<()> {
+        self.0.key(key, direction)
+    }
+
+    fn raw(&mut self, keycode: u16, direction: Direction) -> InputResult<()> {
+        self.0.raw(keycode, direction)
+    }
+}
+
+impl Backend for EnigoBackend {
+    fn name(&self) -> &'static str {
+        if cfg!(target_os = "linux") {
+            "x11"
+        } else {
+            "native"
+        }
+    }
+}
+
+fn open_enigo() -> Result<Box<dyn Backend>, String> {
+    Enigo::new(&Settings::default())
+        .map(|enigo| Box::new(EnigoBackend(enigo)) as Box<dyn Backend>)
+        .map_err(|e| e.to_string())
+}
+
+#[cfg(target_os = "linux")]
+fn open_kind(kind: BackendKind) -> Result<Box<dyn Backend>, String> {
+    match kind {
+        BackendKind::X11 => open_enigo(),
+        BackendKind::Uinput => Ok(Box::new(uinput::open()?)),
+        BackendKind::Wayland => Ok(Box::new(wayland::open()?)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn open_kind(kind: BackendKind) -> Result<Box<dyn Backend>, String> {
+    Err(format!(
+        "the {} backend is only available on Linux",
+        kind.name()
+    ))
+}
+
+// XTest only reaches XWayland clients in a Wayland session, so it is never
+// picked there automatically; uinput works everywhere it is permitted.
+#[cfg(target_os = "linux")]
+fn candidates() -> Vec<BackendKind> {
+    let has = |name: &str| std::env::var_os(name).is_some_and(|value| !value.is_empty());
+    if has("WAYLAND_DISPLAY") {
+        vec![BackendKind::Wayland, BackendKind::Uinput]
+    } else if has("DISPLAY") {
+        vec![BackendKind::X11, BackendKind::Uinput]
+    } else {
+        vec![BackendKind::Uinput]
+    }
+}
+
+// Opens the requested backend, or the first one that works for the session.
+pub fn open(kind: Option<BackendKind>) -> Result<Box<dyn Backend>, BackendError> {
+    let mut attempts = Vec::new();
+
+    #[cfg(target_os = "linux")]
+    let candidates = kind.map_or_else(candidates, |kind| vec![kind]);
+    #[cfg(not(target_os = "linux"))]
+    let candidates = match kind {
+        Some(kind) => vec![kind],
+        None => {
+            return open_enigo().map_err(|e| BackendError {
+                role: "input",
+                attempts: vec![("native", e)],
+            })
+        }
+    };
+
+    for kind in candidates {
+        match open_kind(kind) {
+            Ok(backend) => return Ok(backend),
+            Err(reason) => attempts.push((kind.name(), reason)),
+        }
+    }
+    Err(BackendError {
+        role: "input",
+        attempts,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn error_names_every_failed_backend() {
+        let error = BackendError {
+            role: "input",
+            attempts: vec![("wayland", "no protocol".to_string())],
+        };
+        assert_eq!(
+            error.to_string(),
+            "The wayland input backend failed: no protocol"
+        );
+
+        let error = BackendError {
+            role: "input",
+            attempts: vec![
+                ("wayland", "no protocol".to_string()),
+                ("uinput", "no permission".to_string()),
+            ],
+        };
+        assert_eq!(
+            error.to_string(),
+            "No input backend works: wayland: no protocol; uinput: no permission"
+        );
+    }
+
+    // Run under Xvfb: `xvfb-run cargo test -- --ignored`
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn x11_backend_types_through_xtest() {
+        let mut backend = open(Some(BackendKind::X11)).unwrap();
+        assert_eq!(backend.name(), "x11");
+        backend.text("hello").unwrap();
+        backend.key(Key::Return, Direction::Click).unwrap();
+    }
+}
@@ -0,0 +1,171 @@
+use std::io;
+use std::time::Duration;
+
+use enigo::{Direction, InputError, InputResult, Key, Keyboard};
+use evdev::uinput::VirtualDevice;
+use evdev::{AttributeSet, KeyCode, KeyEvent};
+
+use super::keycodes::{named_key, us_layout, KEY_LEFTSHIFT, NAMED_KEYS};
+use super::Backend;
+
+const DEVICE_NAME: &str = "SpeakMCP virtual keyboard";
+
+// The compositor needs a moment to pick up a new input device, events sent
+// before that are lost.
+const DEVICE_SETTLE: Duration = Duration::from_millis(200);
+
+// Where key events end up, a uinput device or a recorder in tests.
+pub trait KeySink {
+    fn emit(&mut self, code: u16, pressed: bool) -> io::Result<()>;
+}
+
+impl KeySink for VirtualDevice {
+    fn emit(&mut self, code: u16, pressed: bool) -> io::Result<()> {
+        VirtualDevice::emit(self, &[*KeyEvent::new(KeyCode(code), pressed as i32)])
+    }
+}
+
+// Types through a virtual keyboard created with /dev/uinput. It sits below
+// the display server so it works under X11, Wayland and on the console, but
+// it sends key codes, which the session interprets with its own layout. We
+// assume US QWERTY and refuse characters that layout can't type.
+pub struct UinputBackend<S: KeySink = VirtualDevice> {
+    sink: S,
+    shift_held: bool,
+}
+
+pub fn open() -> Result<UinputBackend, String> {
+    let mut keys = AttributeSet::<KeyCode>::new();
+    for key in NAMED_KEYS {
+        if let Some((code, _, _)) = named_key(*key) {
+            keys.insert(KeyCode(code));
+        }
+    }
+    for c in ('!'..='~').filter_map(us_layout) {
+        keys.insert(KeyCode(c.0));
+    }
+
+    let device = VirtualDevice::builder()
+        .and_then(|builder| builder.name(DEVICE_NAME).with_keys(&keys))
+        .and_then(|builder| builder.build())
+        .map_err(|e| match e.kind() {
+            io::ErrorKind::NotFound => {
+                "/dev/uinput does not exist, load the uinput kernel module".to_string()
+            }
+            io::ErrorKind::PermissionDenied => "no permission to open /dev/uinput, add a udev \
+                rule granting access or add the user to the input group"
+                .to_string(),
+            _ => format!("failed to create the virtual keyboard: {}", e),
+        })?;
+    std::thread::sleep(DEVICE_SETTLE);
+    Ok(UinputBackend::new(device))
+}
+
+impl<S: KeySink> UinputBackend<S> {
+    pub fn new(sink: S) -> Self {
+        UinputBackend {
+            sink,
+            shift_held: false,
+        }
+    }
+
+    fn send(&mut self, code: u16, direction: Direction) -> InputResult<()> {
+        let emit = |sink: &mut S, pressed| {
+            sink.emit(code, pressed)
+                .map_err(|_| InputError::Simulate("failed to write to /dev/uinput"))
+        };
+        if matches!(direction, Direction::Press | Direction::Click) {
+            emit(&mut self.sink, true)?;
+        }
+        if matches!(direction, Direction::Release | Direction::Click) {
+            emit(&mut self.sink, false)?;
+        }
+        Ok(())
+    }
+}
+
+impl<S: KeySink> Keyboard for UinputBackend<S> {
+    fn fast_text(&mut self, _text: &str) -> InputResult<Option<()>> {
+        // Key by key through `key`
+        Ok(None)
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> InputResult<()> {
+        if let Some((code, _, _)) = named_key(key) {
+            if code == KEY_LEFTSHIFT && direction != Direction::Click {
+                self.shift_held = direction == Direction::Press;
+            }
+            return self.send(code, direction);
+        }
+
+        let Key::Unicode(c) = key else {
+            return Err(InputError::Mapping(format!(
+                "{:?} is not supported by uinput",
+                key
+            )));
+        };
+        let (code, shift) = us_layout(c).ok_or_else(|| {
+            InputError::Mapping(format!(
+                "{:?} can't be typed with the US layout uinput assumes",
+                c
+            ))
+        })?;
+        // Shortcuts like Ctrl+a press the key and release it separately,
+        // only clicks get an automatic Shift.
+        if !shift || self.shift_held || direction != Direction::Click {
+            return self.send(code, direction);
+        }
+        self.send(KEY_LEFTSHIFT, Direction::Press)?;
+        let result = self.send(code, Direction::Click);
+        self.send(KEY_LEFTSHIFT, Direction::Release)?;
+        result
+    }
+
+    fn raw(&mut self, keycode: u16, direction: Direction) -> InputResult<()> {
+        self.send(keycode, direction)
+    }
+}
+
+impl<S: KeySink> Backend for UinputBackend<S> {
+    fn name(&self) -> &'static str {
+        "uinput"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    impl KeySink for Vec<(u16, bool)> {
+        fn emit(&mut self, code: u16, pressed: bool) -> io::Result<()> {
+            self.push((code, pressed));
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn types_text_and_chords_on_mock_device() {
+        let mut backend = UinputBackend::new(Vec::new());
+        backend.text("a!").unwrap();
+        backend.key(Key::Control, Direction::Press).unwrap();
+        backend.key(Key::Unicode('v'), Direction::Click).unwrap();
+        backend.key(Key::Control, Direction::Release).unwrap();
+
+        assert_eq!(
+            backend.sink,
+            vec![
+                (30, true),
+                (30, false),
+                (KEY_LEFTSHIFT, true),
+                (2, true),
+                (2, false),
+                (KEY_LEFTSHIFT, false),
+                (29, true),
+                (47, true),
+                (47, false),
+                (29, false),
+            ]
+        );
+        assert!(backend.text("ü").is_err());
+    }
+}
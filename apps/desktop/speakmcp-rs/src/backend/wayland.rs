@@ -0,0 +1,301 @@
+use std::io::Write;
+use std::os::fd::AsFd;
+use std::time::Instant;
+
+use enigo::{Direction, InputError, InputResult, Key, Keyboard};
+use wayland_client::globals::{registry_queue_init, GlobalListContents};
+use wayland_client::protocol::wl_keyboard::KeymapFormat;
+use wayland_client::protocol::wl_registry::WlRegistry;
+use wayland_client::protocol::wl_seat::WlSeat;
+use wayland_client::{delegate_noop, Connection, Dispatch, EventQueue, QueueHandle};
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_manager_v1::ZwpVirtualKeyboardManagerV1;
+use wayland_protocols_misc::zwp_virtual_keyboard_v1::client::zwp_virtual_keyboard_v1::ZwpVirtualKeyboardV1;
+
+use super::keycodes::{named_key, Modifier, NAMED_KEYS};
+use super::Backend;
+
+// Evdev codes no named key uses, which the keymap assigns to the characters
+// being typed. X11 keycodes (evdev + 8) end at 255, XWayland clients can't
+// see anything above.
+fn char_codes() -> impl Iterator<Item = u16> {
+    (128..=182).chain(191..=247)
+}
+
+const CHAR_SLOTS: usize = 112;
+
+struct State;
+
+impl Dispatch<WlRegistry, GlobalListContents> for State {
+    fn event(
+        _: &mut Self,
+        _: &WlRegistry,
+        _: <WlRegistry as wayland_client::Proxy>::Event,
+        _: &GlobalListContents,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+delegate_noop!(State: ignore WlSeat);
+delegate_noop!(State: ZwpVirtualKeyboardManagerV1);
+delegate_noop!(State: ZwpVirtualKeyboardV1);
+
+fn modifier_mask(modifier: Modifier) -> u32 {
+    // Real modifier bits of the "complete" xkb types
+    match modifier {
+        Modifier::Shift => 1 << 0,
+        Modifier::Control => 1 << 2,
+        Modifier::Alt => 1 << 3,
+        Modifier::Meta => 1 << 6,
+    }
+}
+
+// An xkb keymap with the named keys at their usual codes and `chars` on the
+// spare ones, so any character can be typed without knowing the user's
+// layout.
+pub fn keymap(chars: &[char]) -> String {
+    let mut codes = String::new();
+    let mut symbols = String::new();
+    let mut modifiers: Vec<(&str, u16)> = Vec::new();
+
+    for key in NAMED_KEYS {
+        let Some((code, keysym, modifier)) = named_key(*key) else {
+            continue;
+        };
+        codes.push_str(&format!("        <I{0}> = {0};\n", code + 8));
+        symbols.push_str(&format!(
+            "        key <I{}> {{ [ {} ] }};\n",
+            code + 8,
+            keysym
+        ));
+        if let Some(modifier) = modifier {
+            let name = match modifier {
+                Modifier::Shift => "Shift",
+                Modifier::Control => "Control",
+                Modifier::Alt => "Mod1",
+                Modifier::Meta => "Mod4",
+            };
+            modifiers.push((name, code + 8));
+        }
+    }
+    for (c, code) in chars.iter().zip(char_codes()) {
+        codes.push_str(&format!("        <I{0}> = {0};\n", code + 8));
+        symbols.push_str(&format!(
+            "        key <I{}> {{ [ U{:04X} ] }};\n",
+            code + 8,
+            *c as u32
+        ));
+    }
+    for (name, code) in modifiers {
+        symbols.push_str(&format!(
+            "        modifier_map {} {{ <I{}> }};\n",
+            name, code
+        ));
+    }
+
+    format!(
+        "xkb_keymap {{\n    xkb_keycodes \"speakmcp\" {{\n        minimum = 8;\n        maximum = 255;\n{}    }};\n    xkb_types \"speakmcp\" {{ include \"complete\" }};\n    xkb_compatibility \"speakmcp\" {{ include \"complete\" }};\n    xkb_symbols \"speakmcp\" {{\n{}    }};\n}};\n",
+        codes, symbols
+    )
+}
+
+// Types through the zwp_virtual_keyboard_v1 protocol of wlroots based
+// compositors (Sway, Hyprland, ...). The keymap is ours, so text doesn't
+// depend on the user's layout.
+pub struct WaylandBackend {
+    connection: Connection,
+    queue: EventQueue<State>,
+    keyboard: ZwpVirtualKeyboardV1,
+    // Characters in the current keymap, in char_codes() order
+    chars: Vec<char>,
+    modifiers: u32,
+    started: Instant,
+}
+
+pub fn open() -> Result<WaylandBackend, String> {
+    let connection = Connection::connect_to_env()
+        .map_err(|e| format!("can't connect to the Wayland compositor: {}", e))?;
+    let (globals, queue) = registry_queue_init::<State>(&connection)
+        .map_err(|e| format!("can't list Wayland globals: {}", e))?;
+    let handle = queue.handle();
+
+    let seat: WlSeat = globals
+        .bind(&handle, 1..=1, ())
+        .map_err(|_| "the compositor has no seat".to_string())?;
+    let manager: ZwpVirtualKeyboardManagerV1 = globals.bind(&handle, 1..=1, ()).map_err(|_| {
+        "the compositor does not support the virtual keyboard protocol \
+            (zwp_virtual_keyboard_manager_v1), GNOME for example doesn't"
+            .to_string()
+    })?;
+    let keyboard = manager.create_virtual_keyboard(&seat, &handle, ());
+
+    let mut backend = WaylandBackend {
+        connection,
+        queue,
+        keyboard,
+        chars: Vec::new(),
+        modifiers: 0,
+        started: Instant::now(),
+    };
+    backend
+        .upload_keymap()
+        .map_err(|e| format!("the compositor refused the virtual keyboard: {}", e))?;
+    Ok(backend)
+}
+
+impl WaylandBackend {
+    fn upload_keymap(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let keymap = keymap(&self.chars);
+        let mut file = tempfile::tempfile()?;
+        file.write_all(keymap.as_bytes())?;
+        // The size includes a terminating NUL
+        file.write_all(&[0])?;
+        self.keyboard.keymap(
+            KeymapFormat::XkbV1.into(),
+            file.as_fd(),
+            keymap.len() as u32 + 1,
+        );
+        // Protocol errors (an unauthorized client) show up here
+        self.queue.roundtrip(&mut State)?;
+        Ok(())
+    }
+
+    // Makes sure the keymap has a code for each of `chars`, which must fit
+    // into CHAR_SLOTS.
+    fn load_chars(&mut self, chars: &[char]) -> InputResult<()> {
+        let missing: Vec<char> = chars
+            .iter()
+            .filter(|c| !self.chars.contains(c))
+            .copied()
+            .collect();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        // Start over once the spare codes run out
+        if self.chars.len() + missing.len() > CHAR_SLOTS {
+            self.chars = chars.to_vec();
+        } else {
+            self.chars.extend(missing);
+        }
+        self.upload_keymap()
+            .map_err(|_| InputError::Simulate("failed to upload the Wayland keymap"))
+    }
+
+    fn char_code(&self, c: char) -> Option<u16> {
+        let slot = self.chars.iter().position(|loaded| *loaded == c)?;
+        char_codes().nth(slot)
+    }
+
+    fn send(&mut self, code: u16, pressed: bool) -> InputResult<()> {
+        let time = self.started.elapsed().as_millis() as u32;
+        self.keyboard.key(time, code as u32, pressed as u32);
+        self.connection
+            .flush()
+            .map_err(|_| InputError::Simulate("the Wayland connection failed"))
+    }
+
+    fn send_key(
+        &mut self,
+        code: u16,
+        modifier: Option<Modifier>,
+        direction: Direction,
+    ) -> InputResult<()> {
+        let mask = modifier.map(modifier_mask).unwrap_or(0);
+        if matches!(direction, Direction::Press | Direction::Click) {
+            self.send(code, true)?;
+            self.set_modifiers(self.modifiers | mask)?;
+        }
+        if matches!(direction, Direction::Release | Direction::Click) {
+            self.send(code, false)?;
+            self.set_modifiers(self.modifiers & !mask)?;
+        }
+        Ok(())
+    }
+
+    fn type_run(&mut self, run: &[char], distinct: &[char]) -> InputResult<()> {
+        self.load_chars(distinct)?;
+        for c in run {
+            let code = match named_key(Key::Unicode(*c)) {
+                Some((code, _, _)) => code,
+                None => self.char_code(*c).unwrap(),
+            };
+            self.send(code, true)?;
+            self.send(code, false)?;
+        }
+        Ok(())
+    }
+
+    // Clients take modifier state from this request, not from key events
+    fn set_modifiers(&mut self, modifiers: u32) -> InputResult<()> {
+        if modifiers == self.modifiers {
+            return Ok(());
+        }
+        self.modifiers = modifiers;
+        self.keyboard.modifiers(modifiers, 0, 0, 0);
+        self.connection
+            .flush()
+            .map_err(|_| InputError::Simulate("the Wayland connection failed"))
+    }
+}
+
+impl Keyboard for WaylandBackend {
+    fn fast_text(&mut self, text: &str) -> InputResult<Option<()>> {
+        let mut run: Vec<char> = Vec::new();
+        let mut distinct: Vec<char> = Vec::new();
+        for c in text.chars() {
+            let needs_slot = named_key(Key::Unicode(c)).is_none();
+            if needs_slot && !distinct.contains(&c) {
+                if distinct.len() == CHAR_SLOTS {
+                    self.type_run(&run, &distinct)?;
+                    run.clear();
+                    distinct.clear();
+                }
+                distinct.push(c);
+            }
+            run.push(c);
+        }
+        self.type_run(&run, &distinct)?;
+        Ok(Some(()))
+    }
+
+    fn key(&mut self, key: Key, direction: Direction) -> InputResult<()> {
+        if let Some((code, _, modifier)) = named_key(key) {
+            return self.send_key(code, modifier, direction);
+        }
+        let Key::Unicode(c) = key else {
+            return Err(InputError::Mapping(format!(
+                "{:?} is not supported on Wayland",
+                key
+            )));
+        };
+        self.load_chars(&[c])?;
+        let code = self.char_code(c).unwrap();
+        self.send_key(code, None, direction)
+    }
+
+    fn raw(&mut self, keycode: u16, direction: Direction) -> InputResult<()> {
+        self.send_key(keycode, None, direction)
+    }
+}
+
+impl Backend for WaylandBackend {
+    fn name(&self) -> &'static str {
+        "wayland"
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keymap_maps_characters_to_spare_codes() {
+        assert_eq!(char_codes().count(), CHAR_SLOTS);
+        let keymap = keymap(&['é', '€']);
+        assert!(keymap.contains("key <I36> { [ Return ] };"));
+        assert!(keymap.contains("key <I136> { [ U00E9 ] };"));
+        assert!(keymap.contains("key <I137> { [ U20AC ] };"));
+        assert!(keymap.contains("modifier_map Control { <I37> };"));
+    }
+}
@@ -0,0 +1,629 @@
+use std::collections::HashSet;
+use std::fs::OpenOptions;
+use std::io;
+use std::os::fd::AsRawFd;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use evdev::uinput::VirtualDevice;
+use evdev::{Device, EventType, InputEvent, KeyCode, SynchronizationCode};
+use rdev::{Button, Event};
+
+use crate::layout::{code_from_key, key_from_code};
+
+const DEVICE_DIR: &str = "/dev/input";
+
+// How often to look for devices plugged in after we started
+const RESCAN_INTERVAL: Duration = Duration::from_secs(2);
+
+const INPUT_GROUP_HINT: &str = "no permission to read /dev/input/event*, add the user to the \
+    input group (sudo usermod -aG input $USER) and log in again";
+
+// Mouse, joystick and gamepad buttons sit between the keyboard keys
+const BUTTONS: std::ops::Range<u16> = 0x100..0x160;
+
+// BTN_LEFT, BTN_RIGHT, BTN_MIDDLE, then BTN_SIDE, BTN_EXTRA, BTN_FORWARD,
+// BTN_BACK and BTN_TASK, which X11 numbers from 8
+const MOUSE_BUTTONS: std::ops::Range<u16> = 0x110..0x118;
+
+const REL_HWHEEL: u16 = 0x06;
+const REL_WHEEL: u16 = 0x08;
+
+// Grabbed keyboards reach the session again through one of these
+const PASSTHROUGH_NAME: &str = "SpeakMCP passthrough keyboard";
+
+// Prefix of the virtual devices we create, which are never grabbed
+const OWN_DEVICE_PREFIX: &str = "SpeakMCP";
+
+// Keys held while grabbing would stay pressed for the session, so wait this
+// long for them to be released first.
+const GRAB_SETTLE: Duration = Duration::from_secs(2);
+
+// Events left unprocessed this long mean the listener is stuck. The readers
+// then release their keyboards, so a hang never takes the keyboard away. A
+// crash needs nothing, the kernel drops the grab with the file.
+const GRAB_TIMEOUT: Duration = Duration::from_secs(1);
+
+// Whether `device` has events to read before `timeout` runs out
+fn wait_readable(device: &Device, timeout: Duration) -> io::Result<bool> {
+    let mut fd = libc::pollfd {
+        fd: device.as_raw_fd(),
+        events: libc::POLLIN,
+        revents: 0,
+    };
+    match unsafe { libc::poll(&mut fd, 1, timeout.as_millis() as libc::c_int) } {
+        -1 => match io::Error::last_os_error() {
+            e if e.kind() == io::ErrorKind::Interrupted => Ok(false),
+            e => Err(e),
+        },
+        ready => Ok(ready > 0),
+    }
+}
+
+// Hands events read from a grabbed device to its virtual twin as they
+// came, one report at a time. Scan codes and the like are left out.
+fn replay(device: &mut VirtualDevice, events: &[InputEvent]) -> io::Result<()> {
+    let mut report = Vec::new();
+    for event in events {
+        let event_type = event.event_type();
+        if event_type == EventType::KEY || event_type == EventType::RELATIVE {
+            report.push(*event);
+        } else if event_type == EventType::SYNCHRONIZATION
+            && event.code() == SynchronizationCode::SYN_REPORT.0
+            && !report.is_empty()
+        {
+            device.emit(&report)?;
+            report.clear();
+        }
+    }
+    if !report.is_empty() {
+        device.emit(&report)?;
+    }
+    Ok(())
+}
+
+fn button_from_code(code: u16) -> Button {
+    match code - MOUSE_BUTTONS.start {
+        0 => Button::Left,
+        1 => Button::Right,
+        2 => Button::Middle,
+        n => Button::Unknown(n as u8 + 5),
+    }
+}
+
+// The rdev event for a raw input event, None for anything rdev wouldn't
+// report. Auto-repeat (value 2) is a press, like X11 reports it. Pointer
+// motion is relative here, so there are no MouseMove events.
+pub fn translate(time: SystemTime, event_type: u16, code: u16, value: i32) -> Option<Event> {
+    let event_type = if event_type == EventType::RELATIVE.0 {
+        match code {
+            REL_WHEEL => rdev::EventType::Wheel {
+                delta_x: 0,
+                delta_y: value as i64,
+            },
+            REL_HWHEEL => rdev::EventType::Wheel {
+                delta_x: value as i64,
+                delta_y: 0,
+            },
+            _ => return None,
+        }
+    } else if event_type != EventType::KEY.0 {
+        return None;
+    } else if MOUSE_BUTTONS.contains(&code) {
+        let button = button_from_code(code);
+        match value {
+            0 => rdev::EventType::ButtonRelease(button),
+            1 => rdev::EventType::ButtonPress(button),
+            _ => return None,
+        }
+    } else if BUTTONS.contains(&code) {
+        return None;
+    } else {
+        let key = key_from_code(code);
+        match value {
+            0 => rdev::EventType::KeyRelease(key),
+            1 | 2 => rdev::EventType::KeyPress(key),
+            _ => return None,
+        }
+    };
+    Some(Event {
+        time,
+        name: None,
+        event_type,
+    })
+}
+
+fn code_from_button(button: Button) -> Option<u16> {
+    let code = match button {
+        Button::Left => 0,
+        Button::Right => 1,
+        Button::Middle => 2,
+        Button::Unknown(n) => (n as u16).checked_sub(5)?,
+    };
+    Some(MOUSE_BUTTONS.start + code).filter(|code| MOUSE_BUTTONS.contains(code))
+}
+
+// The raw input event for an event the grab passes on
+fn untranslate(event_type: rdev::EventType) -> Option<InputEvent> {
+    let (event_type, code, value) = match event_type {
+        rdev::EventType::KeyPress(key) => (EventType::KEY, code_from_key(key)?, 1),
+        rdev::EventType::KeyRelease(key) => (EventType::KEY, code_from_key(key)?, 0),
+        rdev::EventType::ButtonPress(button) => (EventType::KEY, code_from_button(button)?, 1),
+        rdev::EventType::ButtonRelease(button) => (EventType::KEY, code_from_button(button)?, 0),
+        rdev::EventType::Wheel { delta_x: 0, delta_y } => (EventType::RELATIVE, REL_WHEEL, delta_y),
+        rdev::EventType::Wheel { delta_x, .. } => (EventType::RELATIVE, REL_HWHEEL, delta_x),
+        rdev::EventType::MouseMove { .. } => return None,
+    };
+    Some(InputEvent::new(event_type.0, code, value as i32))
+}
+
+fn is_keyboard(device: &Device) -> bool {
+    device
+        .supported_keys()
+        .is_some_and(|keys| keys.contains(KeyCode::KEY_A) && keys.contains(KeyCode::KEY_ENTER))
+}
+
+fn is_mouse(device: &Device) -> bool {
+    device.supported_keys().is_some_and(|keys| {
+        MOUSE_BUTTONS
+            .clone()
+            .any(|code| keys.contains(KeyCode(code)))
+    })
+}
+
+struct Scan {
+    // Keyboards and mice
+    devices: Vec<(PathBuf, Device)>,
+    // Event devices we weren't allowed to open
+    denied: usize,
+}
+
+fn scan(skip: &HashSet<PathBuf>) -> io::Result<Scan> {
+    let mut scan = Scan {
+        devices: Vec::new(),
+        denied: 0,
+    };
+    for entry in std::fs::read_dir(DEVICE_DIR)? {
+        let path = entry?.path();
+        let is_event_node = path
+            .file_name()
+            .and_then(|name| name.to_str())
+            .is_some_and(|name| name.starts_with("event"));
+        if !is_event_node || skip.contains(&path) {
+            continue;
+        }
+        match Device::open(&path) {
+            Ok(device) if device.name() == Some(PASSTHROUGH_NAME) => {}
+            Ok(device) if is_keyboard(&device) || is_mouse(&device) => {
+                scan.devices.push((path, device))
+            }
+            Ok(_) => {}
+            Err(e) if e.kind() == io::ErrorKind::PermissionDenied => scan.denied += 1,
+            // Unplugged while we were looking
+            Err(_) => {}
+        }
+    }
+    Ok(scan)
+}
+
+// Reads every keyboard and mouse at once, so the listener works under
+// Wayland and on the console too. Needs read access to /dev/input, usually
+// through the input group, and /dev/uinput to grab keyboards.
+pub struct EvdevCapture {
+    devices: Vec<(PathBuf, Device)>,
+    grab: bool,
+}
+
+pub fn open(grab: bool) -> Result<EvdevCapture, String> {
+    let scan = scan(&HashSet::new()).map_err(|e| format!("can't list {}: {}", DEVICE_DIR, e))?;
+    if scan.devices.is_empty() {
+        return Err(if scan.denied > 0 {
+            INPUT_GROUP_HINT.to_string()
+        } else {
+            format!("no keyboard or mouse found in {}", DEVICE_DIR)
+        });
+    }
+    if grab {
+        OpenOptions::new()
+            .write(true)
+            .open("/dev/uinput")
+            .map_err(|e| match e.kind() {
+                io::ErrorKind::NotFound => {
+                    "grabbing needs /dev/uinput, load the uinput kernel module".to_string()
+                }
+                io::ErrorKind::PermissionDenied => "grabbing needs /dev/uinput, add a udev \
+                    rule granting access or add the user to the input group"
+                    .to_string(),
+                _ => format!("can't open /dev/uinput: {}", e),
+            })?;
+    }
+    Ok(EvdevCapture {
+        devices: scan.devices,
+        grab,
+    })
+}
+
+fn passthrough(device: &Device) -> io::Result<VirtualDevice> {
+    let mut builder = VirtualDevice::builder()?.name(PASSTHROUGH_NAME);
+    if let Some(keys) = device.supported_keys() {
+        builder = builder.with_keys(keys)?;
+    }
+    // Receivers often put the mouse on the keyboard's device
+    if let Some(axes) = device.supported_relative_axes() {
+        builder = builder.with_relative_axes(axes)?;
+    }
+    builder.build()
+}
+
+// Takes `device` exclusively, returning the virtual device its events
+// have to be passed on to.
+fn grab(path: &Path, device: &mut Device) -> Result<VirtualDevice, String> {
+    let passthrough =
+        passthrough(device).map_err(|e| format!("can't create the passthrough keyboard: {}", e))?;
+    let deadline = Instant::now() + GRAB_SETTLE;
+    while Instant::now() < deadline
+        && device
+            .get_key_state()
+            .is_ok_and(|keys| keys.iter().next().is_some())
+    {
+        thread::sleep(Duration::from_millis(10));
+    }
+    device
+        .grab()
+        .map_err(|e| format!("can't grab {}: {}", path.display(), e))?;
+    Ok(passthrough)
+}
+
+// Shared with the reader, which replays to it what it read when it finds
+// the listener stuck
+type SharedDevice = Arc<Mutex<VirtualDevice>>;
+
+enum Message {
+    // A device was grabbed, its events go to this virtual device
+    Grabbed(usize, SharedDevice),
+    Event(usize, InputEvent),
+    // The device went away or was released by the watchdog
+    Released(usize),
+}
+
+#[derive(Clone)]
+struct Readers {
+    messages: Sender<Message>,
+    // Paths being read, so rescans skip them
+    open: Arc<Mutex<HashSet<PathBuf>>>,
+    // Since when events of grabbed devices wait for the listener
+    waiting: Arc<Mutex<Option<Instant>>>,
+    grab: bool,
+}
+
+impl Readers {
+    // Forwards the events of one device until it goes away.
+    fn spawn(&self, index: usize, path: PathBuf, mut device: Device) {
+        self.open.lock().unwrap().insert(path.clone());
+        let readers = self.clone();
+        thread::spawn(move || {
+            let own = device
+                .name()
+                .is_some_and(|name| name.starts_with(OWN_DEVICE_PREFIX));
+            // The passthrough while the device is grabbed
+            let mut grabbed = None;
+            // Mice are left alone
+            if readers.grab && !own && is_keyboard(&device) {
+                match grab(&path, &mut device) {
+                    Ok(passthrough) => {
+                        let passthrough = Arc::new(Mutex::new(passthrough));
+                        grabbed = Some(passthrough.clone());
+                        let _ = readers.messages.send(Message::Grabbed(index, passthrough));
+                    }
+                    Err(e) => eprintln!("Listening without grabbing: {}", e),
+                }
+            }
+
+            loop {
+                // Grabbed devices look at the listener even without input,
+                // so a hang is noticed before the next keystroke
+                if grabbed.is_some() {
+                    match wait_readable(&device, GRAB_TIMEOUT) {
+                        Ok(true) => {}
+                        Ok(false) => {
+                            if readers.stalled() {
+                                readers.release(index, &path, &mut device, &mut grabbed, &[]);
+                            }
+                            continue;
+                        }
+                        Err(_) => break,
+                    }
+                }
+                // Collected so the device is free for ungrabbing
+                let Ok(events) = device.fetch_events().map(Iterator::collect::<Vec<_>>) else {
+                    break;
+                };
+                if grabbed.is_some() && readers.stalled() {
+                    // The stuck listener would never pass these on
+                    readers.release(index, &path, &mut device, &mut grabbed, &events);
+                    continue;
+                }
+                for event in events {
+                    if grabbed.is_some() {
+                        readers
+                            .waiting
+                            .lock()
+                            .unwrap()
+                            .get_or_insert_with(Instant::now);
+                    }
+                    if readers.messages.send(Message::Event(index, event)).is_err() {
+                        return;
+                    }
+                }
+            }
+            readers.open.lock().unwrap().remove(&path);
+            let _ = readers.messages.send(Message::Released(index));
+        });
+    }
+
+    // Gives the device back to the session, with the events read from it
+    // that the listener didn't get to
+    fn release(
+        &self,
+        index: usize,
+        path: &Path,
+        device: &mut Device,
+        grabbed: &mut Option<SharedDevice>,
+        pending: &[InputEvent],
+    ) {
+        let Some(passthrough) = grabbed.take() else {
+            return;
+        };
+        let _ = device.ungrab();
+        let _ = replay(&mut passthrough.lock().unwrap(), pending);
+        eprintln!(
+            "The listener stopped responding, released {}",
+            path.display()
+        );
+        let _ = self.messages.send(Message::Released(index));
+    }
+
+    fn stalled(&self) -> bool {
+        self.waiting
+            .lock()
+            .unwrap()
+            .is_some_and(|since| since.elapsed() > GRAB_TIMEOUT)
+    }
+}
+
+// A grabbed device's events on their way to its virtual twin, one report
+// at a time.
+struct Passthrough {
+    device: SharedDevice,
+    report: Vec<InputEvent>,
+}
+
+impl Passthrough {
+    fn handle<F>(&mut self, event: InputEvent, callback: &mut F)
+    where
+        F: FnMut(Event) -> Vec<rdev::EventType>,
+    {
+        let event_type = event.event_type();
+        if event_type == EventType::SYNCHRONIZATION {
+            if event.code() == SynchronizationCode::SYN_REPORT.0 && !self.report.is_empty() {
+                // Emitting adds the SYN_REPORT
+                let _ = self.device.lock().unwrap().emit(&self.report);
+                self.report.clear();
+            }
+            return;
+        }
+        let key = translate(event.timestamp(), event_type.0, event.code(), event.value());
+        if let Some(key) = key {
+            let forward = callback(key);
+            self.report
+                .extend(forward.into_iter().filter_map(untranslate));
+        } else if event_type == EventType::KEY || event_type == EventType::RELATIVE {
+            // Other buttons and motion
+            self.report.push(event);
+        }
+        // Scan codes and the like are left out
+    }
+}
+
+impl EvdevCapture {
+    // Never returns, devices that disappear are picked up again when
+    // they come back.
+    pub fn run<F>(self, mut callback: F) -> Result<(), String>
+    where
+        F: FnMut(Event) -> Vec<rdev::EventType> + Send + 'static,
+    {
+        let (messages, rx) = mpsc::channel();
+        let readers = Readers {
+            messages,
+            open: Arc::new(Mutex::new(HashSet::new())),
+            waiting: Arc::new(Mutex::new(None)),
+            grab: self.grab,
+        };
+        // By reader index, Some while the device is grabbed
+        let mut passthroughs: Vec<Option<Passthrough>> = Vec::new();
+        for (path, device) in self.devices {
+            readers.spawn(passthroughs.len(), path, device);
+            passthroughs.push(None);
+        }
+
+        loop {
+            match rx.recv_timeout(RESCAN_INTERVAL) {
+                Ok(Message::Grabbed(index, device)) => {
+                    passthroughs[index] = Some(Passthrough {
+                        device,
+                        report: Vec::new(),
+                    });
+                }
+                Ok(Message::Event(index, event)) => {
+                    match &mut passthroughs[index] {
+                        Some(passthrough) => passthrough.handle(event, &mut callback),
+                        None => {
+                            let key = translate(
+                                event.timestamp(),
+                                event.event_type().0,
+                                event.code(),
+                                event.value(),
+                            );
+                            if let Some(key) = key {
+                                callback(key);
+                            }
+                        }
+                    }
+                    *readers.waiting.lock().unwrap() = None;
+                }
+                Ok(Message::Released(index)) => passthroughs[index] = None,
+                Err(RecvTimeoutError::Timeout) => {
+                    let known = readers.open.lock().unwrap().clone();
+                    if let Ok(scan) = scan(&known) {
+                        for (path, device) in scan.devices {
+                            readers.spawn(passthroughs.len(), path, device);
+                            passthroughs.push(None);
+                        }
+                    }
+                }
+                // `readers` holds a sender itself
+                Err(RecvTimeoutError::Disconnected) => unreachable!(),
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotkey::{Binding, HotkeyEngine, HotkeyEvent};
+    use crate::listener::{Listener, Output};
+    use rdev::Key;
+
+    // `evtest` output of a laptop keyboard: Ctrl held past the hold delay
+    // with auto-repeat, then Ctrl+/ tapped, scan codes and sync reports
+    // included.
+    const RECORDING: &str = "\
+Event: time 1700000000.000000, type 4 (EV_MSC), code 4 (MSC_SCAN), value 1d
+Event: time 1700000000.000000, type 1 (EV_KEY), code 29 (KEY_LEFTCTRL), value 1
+Event: time 1700000000.000000, -------------- SYN_REPORT ------------
+Event: time 1700000000.500112, type 1 (EV_KEY), code 29 (KEY_LEFTCTRL), value 2
+Event: time 1700000000.500112, -------------- SYN_REPORT ------------
+Event: time 1700000000.533417, type 1 (EV_KEY), code 29 (KEY_LEFTCTRL), value 2
+Event: time 1700000000.533417, -------------- SYN_REPORT ------------
+Event: time 1700000000.866703, type 1 (EV_KEY), code 29 (KEY_LEFTCTRL), value 2
+Event: time 1700000000.866703, -------------- SYN_REPORT ------------
+Event: time 1700000001.200000, type 4 (EV_MSC), code 4 (MSC_SCAN), value 1d
+Event: time 1700000001.200000, type 1 (EV_KEY), code 29 (KEY_LEFTCTRL), value 0
+Event: time 1700000001.200000, -------------- SYN_REPORT ------------
+Event: time 1700000003.000000, type 1 (EV_KEY), code 97 (KEY_RIGHTCTRL), value 1
+Event: time 1700000003.000000, -------------- SYN_REPORT ------------
+Event: time 1700000003.100000, type 1 (EV_KEY), code 53 (KEY_SLASH), value 1
+Event: time 1700000003.100000, -------------- SYN_REPORT ------------
+Event: time 1700000003.180000, type 1 (EV_KEY), code 53 (KEY_SLASH), value 0
+Event: time 1700000003.180000, -------------- SYN_REPORT ------------
+Event: time 1700000003.250000, type 1 (EV_KEY), code 97 (KEY_RIGHTCTRL), value 0
+Event: time 1700000003.250000, -------------- SYN_REPORT ------------
+";
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(1_700_000_000_000 + ms)
+    }
+
+    // Event lines of an evtest log as (time, type, code, value), the
+    // fields of struct input_event.
+    fn parse_evtest(log: &str) -> Vec<(SystemTime, u16, u16, i32)> {
+        log.lines()
+            .filter_map(|line| {
+                let fields: Vec<&str> = line.strip_prefix("Event: time ")?.split(", ").collect();
+                let number = |field: &str| field.split(' ').nth(1).map(str::to_string);
+                let (secs, micros) = fields[0].split_once('.')?;
+                let time = SystemTime::UNIX_EPOCH
+                    + Duration::from_secs(secs.parse().ok()?)
+                    + Duration::from_micros(micros.parse().ok()?);
+                let event_type = number(fields.get(1)?)?.parse().ok()?;
+                let code = number(fields.get(2)?)?.parse().ok()?;
+                // Scan codes are printed in hex
+                let value = number(fields.get(3)?)?;
+                let value = i32::from_str_radix(&value, 16).ok()?;
+                Some((time, event_type, code, value))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn replays_recorded_stream_through_hotkeys() {
+        let bindings: Vec<Binding> = serde_json::from_str(
+            r#"[{"id":"record","combo":"ctrl","mode":"hold"},
+                {"id":"mcp","combo":"ctrl-slash","mode":"toggle"}]"#,
+        )
+        .unwrap();
+        let mut listener = Listener::default();
+        listener.hotkeys = HotkeyEngine::new(&bindings).unwrap();
+
+        let events = parse_evtest(RECORDING);
+        assert_eq!(events.len(), 11);
+        let hotkeys: Vec<HotkeyEvent> = events
+            .into_iter()
+            .filter_map(|(time, event_type, code, value)| translate(time, event_type, code, value))
+            .flat_map(|event| listener.process(event))
+            .filter_map(|output| match output {
+                Output::Hotkey(event) => Some(event),
+                _ => None,
+            })
+            .collect();
+
+        assert_eq!(
+            hotkeys,
+            vec![
+                HotkeyEvent::HoldStart {
+                    id: "record".to_string(),
+                    time: at(800),
+                    pressed: at(0),
+                },
+                HotkeyEvent::HoldEnd {
+                    id: "record".to_string(),
+                    time: at(1200),
+                    cancelled: false,
+                },
+                HotkeyEvent::Toggled {
+                    id: "mcp".to_string(),
+                    time: at(3100),
+                },
+            ]
+        );
+        assert!(listener.snapshot().keys.is_empty());
+    }
+
+    #[test]
+    fn maps_codes_like_rdev_on_x11() {
+        let time = SystemTime::UNIX_EPOCH;
+        let key = |code| translate(time, 1, code, 1).map(|event| event.event_type);
+        assert_eq!(key(30), Some(rdev::EventType::KeyPress(Key::KeyA)));
+        assert_eq!(key(100), Some(rdev::EventType::KeyPress(Key::AltGr)));
+        assert_eq!(key(126), Some(rdev::EventType::KeyPress(Key::Unknown(134))));
+        for code in 1..0x100 {
+            assert_eq!(code_from_key(key_from_code(code)), Some(code));
+        }
+        // BTN_SIDE, a joystick button, the wheel and relative motion
+        assert_eq!(
+            key(0x113),
+            Some(rdev::EventType::ButtonPress(Button::Unknown(8)))
+        );
+        assert_eq!(key(0x120), None);
+        assert_eq!(
+            translate(time, 2, REL_WHEEL, -1).map(|event| event.event_type),
+            Some(rdev::EventType::Wheel {
+                delta_x: 0,
+                delta_y: -1
+            })
+        );
+        assert!(translate(time, 2, 0, 5).is_none());
+
+        // What a grab passes on goes out as it came in
+        let raw = [(1, 30, 1), (1, 0x110, 1), (1, 0x117, 0), (2, REL_WHEEL, -1), (2, REL_HWHEEL, 2)];
+        for (event_type, code, value) in raw {
+            let event = translate(time, event_type, code, value).unwrap();
+            let event = untranslate(event.event_type).unwrap();
+            assert_eq!((event.event_type().0, event.code(), event.value()), (event_type, code, value));
+        }
+    }
+}
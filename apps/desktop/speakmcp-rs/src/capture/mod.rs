@@ -0,0 +1,162 @@
+use std::str::FromStr;
+
+use rdev::{Event, EventType};
+use serde::Deserialize;
+
+use crate::backend::BackendError;
+
+#[cfg(target_os = "linux")]
+mod evdev;
+
+// Where the listener gets key events from.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum CaptureKind {
+    // rdev's hook: the X11 RECORD extension on Linux, the native hooks on
+    // macOS and Windows
+    Rdev,
+    // The keyboards in /dev/input, read below the display server
+    Evdev,
+}
+
+impl CaptureKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            CaptureKind::Rdev => "rdev",
+            CaptureKind::Evdev => "evdev",
+        }
+    }
+}
+
+impl FromStr for CaptureKind {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "rdev" => Ok(CaptureKind::Rdev),
+            "evdev" => Ok(CaptureKind::Evdev),
+            _ => Err(format!(
+                "Unknown listener backend: {} (expected rdev or evdev)",
+                s
+            )),
+        }
+    }
+}
+
+const NO_GRAB: &str = "can't grab the keyboard, grabbing needs the evdev backend";
+
+// A backend that passed its probe and is ready to run
+enum Capture {
+    Rdev,
+    #[cfg(target_os = "linux")]
+    Evdev(evdev::EvdevCapture),
+}
+
+impl Capture {
+    fn run<F>(self, mut callback: F) -> Result<(), String>
+    where
+        F: FnMut(Event) -> Vec<EventType> + Send + 'static,
+    {
+        match self {
+            Capture::Rdev => rdev::listen(move |event| {
+                callback(event);
+            })
+            .map_err(|e| format!("{:?}", e)),
+            #[cfg(target_os = "linux")]
+            Capture::Evdev(capture) => capture.run(callback),
+        }
+    }
+}
+
+// rdev connects to the X server by itself and only reports a missing display
+// once it runs, so check what it needs up front.
+#[cfg(target_os = "linux")]
+fn probe_rdev() -> Result<Capture, String> {
+    use x11rb::protocol::xproto::ConnectionExt;
+
+    let (conn, _) =
+        x11rb::connect(None).map_err(|e| format!("can't connect to the X server: {}", e))?;
+    let record = conn
+        .query_extension(b"RECORD")
+        .ok()
+        .and_then(|cookie| cookie.reply().ok())
+        .is_some_and(|reply| reply.present);
+    if !record {
+        return Err("the X server lacks the RECORD extension".to_string());
+    }
+    Ok(Capture::Rdev)
+}
+
+#[cfg(target_os = "linux")]
+fn probe(kind: CaptureKind, grab: bool) -> Result<Capture, String> {
+    match kind {
+        CaptureKind::Rdev if grab => Err(NO_GRAB.to_string()),
+        CaptureKind::Rdev => probe_rdev(),
+        CaptureKind::Evdev => Ok(Capture::Evdev(evdev::open(grab)?)),
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn probe(kind: CaptureKind, grab: bool) -> Result<Capture, String> {
+    match kind {
+        CaptureKind::Rdev if grab => Err(NO_GRAB.to_string()),
+        CaptureKind::Rdev => Ok(Capture::Rdev),
+        CaptureKind::Evdev => Err("the evdev backend is only available on Linux".to_string()),
+    }
+}
+
+fn is_wayland() -> bool {
+    std::env::var_os("WAYLAND_DISPLAY").is_some_and(|value| !value.is_empty())
+}
+
+// Under Wayland rdev only sees keys typed into XWayland windows, so it is
+// the fallback there.
+#[cfg(target_os = "linux")]
+fn candidates() -> Vec<CaptureKind> {
+    if is_wayland() {
+        vec![CaptureKind::Evdev, CaptureKind::Rdev]
+    } else if std::env::var_os("DISPLAY").is_some_and(|value| !value.is_empty()) {
+        vec![CaptureKind::Rdev, CaptureKind::Evdev]
+    } else {
+        vec![CaptureKind::Evdev]
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+fn candidates() -> Vec<CaptureKind> {
+    vec![CaptureKind::Rdev]
+}
+
+// Runs the requested backend, or the first one whose probe passes, and
+// blocks for as long as it is healthy. With `grab` the keyboards are taken
+// exclusively and only the events `callback` returns are passed on to the
+// session; otherwise its result is ignored.
+pub fn listen<F>(kind: Option<CaptureKind>, grab: bool, callback: F) -> Result<(), BackendError>
+where
+    F: FnMut(Event) -> Vec<EventType> + Send + 'static,
+{
+    let mut attempts: Vec<(&'static str, String)> = Vec::new();
+    for kind in kind.map_or_else(candidates, |kind| vec![kind]) {
+        match probe(kind, grab) {
+            Ok(capture) => {
+                if kind == CaptureKind::Rdev && is_wayland() {
+                    eprintln!(
+                        "Listening through XWayland, keys typed into Wayland windows are missed"
+                    );
+                    for (name, reason) in &attempts {
+                        eprintln!("  {}: {}", name, reason);
+                    }
+                }
+                return capture.run(callback).map_err(|reason| BackendError {
+                    role: "listener",
+                    attempts: vec![(kind.name(), reason)],
+                });
+            }
+            Err(reason) => attempts.push((kind.name(), reason)),
+        }
+    }
+    Err(BackendError {
+        role: "listener",
+        attempts,
+    })
+}
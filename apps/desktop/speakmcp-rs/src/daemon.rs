@@ -0,0 +1,975 @@
+use std::io::BufRead;
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::mpsc;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::audio::{
+    self, AudioEvent, AudioFormat, Monitor, RecordOptions, Recording, SourceSpec, VadOptions,
+};
+use crate::backend::{self, Backend, BackendKind};
+use crate::capture::CaptureKind;
+use crate::dictate::{self, CommandAction, Vocabularies, VoiceCommand};
+use crate::event::Format;
+use crate::focus::{self, FocusedWindow};
+use crate::grab::Grab;
+use crate::history::{self, History, Insertion, UndoError, UndoMethod};
+use crate::hotkey::Binding;
+use crate::injection::InjectedPolicy;
+use crate::keyseq;
+use crate::listener::{self, Listener, Output, DEFAULT_MOUSE_MOVE_INTERVAL_MS};
+use crate::session::TypingSession;
+use crate::writer::{insert_text, type_text_paced, Strategy, TypingOptions};
+
+// JSON-RPC 2.0 error codes
+pub const PARSE_ERROR: i64 = -32700;
+pub const INVALID_REQUEST: i64 = -32600;
+pub const METHOD_NOT_FOUND: i64 = -32601;
+pub const INVALID_PARAMS: i64 = -32602;
+// Implementation defined server errors
+pub const COMMAND_FAILED: i64 = -32000;
+pub const NOTHING_TO_UNDO: i64 = -32001;
+pub const FOCUS_CHANGED: i64 = -32002;
+
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(100);
+
+#[derive(Serialize, Debug)]
+pub struct RpcError {
+    pub code: i64,
+    pub message: String,
+}
+
+impl RpcError {
+    pub fn new(code: i64, message: impl Into<String>) -> Self {
+        RpcError {
+            code,
+            message: message.into(),
+        }
+    }
+
+    pub fn failed(message: impl std::fmt::Display) -> Self {
+        RpcError::new(COMMAND_FAILED, message.to_string())
+    }
+}
+
+#[derive(Deserialize)]
+struct Request {
+    jsonrpc: Option<String>,
+    #[serde(default)]
+    id: Option<Value>,
+    method: String,
+    #[serde(default)]
+    params: Value,
+    // cancelWrite requests read before this one, any later one cancels it
+    #[serde(skip)]
+    cancels: u64,
+}
+
+#[derive(Deserialize)]
+struct SubscribeParams {
+    #[serde(default)]
+    format: Option<String>,
+    #[serde(default)]
+    private: bool,
+    #[serde(default)]
+    stuck_key_timeout_ms: Option<u64>,
+    #[serde(default)]
+    release_on_focus_change: bool,
+    #[serde(default)]
+    injected: InjectedPolicy,
+    // Mouse buttons, wheel and throttled moves
+    #[serde(default)]
+    mouse: bool,
+    #[serde(default)]
+    mouse_move_interval_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct RegisterHotkeysParams {
+    bindings: Vec<Binding>,
+}
+
+#[derive(Deserialize)]
+struct WriteParams {
+    text: String,
+    #[serde(default)]
+    strategy: Strategy,
+    #[serde(default)]
+    typing: TypingOptions,
+    // Send writeProgress notifications while typing
+    #[serde(default)]
+    progress: bool,
+}
+
+#[derive(Deserialize)]
+struct DictateParams {
+    text: String,
+    #[serde(default = "default_language")]
+    language: String,
+    #[serde(default)]
+    strategy: Strategy,
+    #[serde(default)]
+    typing: TypingOptions,
+    #[serde(default)]
+    progress: bool,
+}
+
+fn default_language() -> String {
+    dictate::DEFAULT_LANGUAGE.to_string()
+}
+
+#[derive(Deserialize)]
+struct SetVoiceCommandsParams {
+    language: String,
+    commands: Vec<VoiceCommand>,
+}
+
+#[derive(Deserialize)]
+struct TypingBeginParams {
+    #[serde(default)]
+    typing: TypingOptions,
+}
+
+#[derive(Deserialize)]
+struct TypingAppendParams {
+    delta: String,
+}
+
+#[derive(Deserialize)]
+struct UndoLastParams {
+    #[serde(default)]
+    method: UndoMethod,
+}
+
+#[derive(Deserialize)]
+struct SourceParams {
+    // Input device by name, the default one when omitted
+    #[serde(default)]
+    device: Option<String>,
+    // A WAV file or silence instead of a device, for testing
+    #[serde(default)]
+    input_file: Option<PathBuf>,
+    #[serde(default)]
+    null: bool,
+}
+
+impl SourceParams {
+    fn is_default(&self) -> bool {
+        self.device.is_none() && self.input_file.is_none() && !self.null
+    }
+
+    fn spec(self) -> SourceSpec {
+        if self.null {
+            SourceSpec::Null
+        } else if let Some(file) = self.input_file {
+            SourceSpec::File(file)
+        } else {
+            SourceSpec::Device(self.device)
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct RecordStartParams {
+    path: PathBuf,
+    // By the file extension when omitted
+    #[serde(default)]
+    format: Option<AudioFormat>,
+    #[serde(flatten)]
+    source: SourceParams,
+    // Begin this far back with the pre-roll buffer, typically the pressed
+    // time of a HotkeyHoldStart
+    #[serde(default)]
+    start_time: Option<SystemTime>,
+    #[serde(default)]
+    max_duration_ms: Option<u64>,
+    // Send vad notifications, and end the recording after silence with
+    // stop_after_silence_ms
+    #[serde(default)]
+    vad: Option<VadOptions>,
+    // Send level notifications every level_interval_ms of audio, not for
+    // the pre-roll a recording starts with
+    #[serde(default)]
+    levels: bool,
+    #[serde(default)]
+    level_interval_ms: Option<u64>,
+}
+
+#[derive(Deserialize)]
+struct PreRollStartParams {
+    #[serde(default = "default_preroll_seconds")]
+    seconds: f64,
+    #[serde(flatten)]
+    source: SourceParams,
+}
+
+fn default_preroll_seconds() -> f64 {
+    audio::DEFAULT_PREROLL_SECONDS
+}
+
+#[derive(Deserialize)]
+struct KeysParams {
+    sequence: String,
+}
+
+pub fn notify(method: &str, params: impl Serialize) {
+    let message = json!({
+        "jsonrpc": "2.0",
+        "method": method,
+        "params": params,
+    });
+    println!("{}", message);
+}
+
+fn notify_output(output: Output) {
+    match output {
+        Output::Event(event) => notify("event", event),
+        Output::Hotkey(event) => notify("hotkey", event),
+        Output::KeyState(snapshot) => notify("keyState", snapshot),
+    }
+}
+
+fn response(id: Value, result: Result<Value, RpcError>) -> Value {
+    match result {
+        Ok(result) => json!({ "jsonrpc": "2.0", "id": id, "result": result }),
+        Err(error) => json!({ "jsonrpc": "2.0", "id": id, "error": error }),
+    }
+}
+
+pub fn parse_params<T: DeserializeOwned>(params: Value) -> Result<T, RpcError> {
+    // Omitted params are treated like an empty object so optional fields work
+    let params = if params.is_null() { json!({}) } else { params };
+    serde_json::from_value(params).map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))
+}
+
+struct Daemon {
+    backend: Option<Box<dyn Backend>>,
+    // Chosen with `serve --backend`, None picks one for the session
+    backend_kind: Option<BackendKind>,
+    // How many cancelWrite requests the stdin thread has read
+    cancels: Arc<AtomicU64>,
+    // How many it had read before the request being handled
+    cancels_before: u64,
+    listener: Arc<Mutex<Listener>>,
+    // Chosen with `serve --listener`, None picks one for the session
+    listener_kind: Option<CaptureKind>,
+    listener_started: bool,
+    // Voice commands set through setVoiceCommands, by language
+    vocabularies: Vocabularies,
+    // Insertions of write and dictate, for undoLast
+    history: History,
+    // Streaming insertion between typing.begin and typing.end/abort
+    session: Option<TypingSession>,
+    // Between record.start and record.stop
+    recording: Option<Recording>,
+    // Between preroll.start and preroll.stop, recordings read from it then
+    preroll: Option<Monitor>,
+}
+
+impl Daemon {
+    fn new(
+        cancels: Arc<AtomicU64>,
+        backend_kind: Option<BackendKind>,
+        listener_kind: Option<CaptureKind>,
+        grab: bool,
+    ) -> Self {
+        let mut listener = Listener::default();
+        if grab {
+            listener.grab = Some(Grab::default());
+        }
+        Daemon {
+            backend: None,
+            backend_kind,
+            cancels,
+            cancels_before: 0,
+            listener: Arc::new(Mutex::new(listener)),
+            listener_kind,
+            listener_started: false,
+            vocabularies: Vocabularies::new(),
+            history: History::default(),
+            session: None,
+            recording: None,
+            preroll: None,
+        }
+    }
+
+    // Reports typing progress as writeProgress notifications, at most every
+    // PROGRESS_INTERVAL, and stops the write once a cancelWrite sent after
+    // it arrived, even one that came while the write was still queued.
+    fn progress(&self, id: &Value, notify_progress: bool) -> impl FnMut(usize, usize) -> bool {
+        let cancels = self.cancels.clone();
+        let cancels_before = self.cancels_before;
+        let id = id.clone();
+        let mut last_progress: Option<Instant> = None;
+        move |typed: usize, total: usize| {
+            if notify_progress
+                && (typed == total
+                    || last_progress.is_none_or(|last| last.elapsed() >= PROGRESS_INTERVAL))
+            {
+                last_progress = Some(Instant::now());
+                notify(
+                    "writeProgress",
+                    json!({ "id": id, "typed": typed, "total": total }),
+                );
+            }
+            cancels.load(Ordering::SeqCst) == cancels_before
+        }
+    }
+
+    fn take_session(&mut self) -> Result<TypingSession, RpcError> {
+        self.session
+            .take()
+            .ok_or_else(|| RpcError::failed("No typing session, call typing.begin first"))
+    }
+
+    // Types the next piece of a session, but only into the window the session
+    // began in. cancelWrite stops it like a write.
+    fn type_in_session(
+        &mut self,
+        id: &Value,
+        session: &mut TypingSession,
+        text: &str,
+    ) -> Result<(), RpcError> {
+        if text.is_empty() {
+            return Ok(());
+        }
+        let window = focus::get_focused_window().ok();
+        if session.focus_changed(window.as_ref()) {
+            return Err(RpcError::new(
+                FOCUS_CHANGED,
+                "Focus moved to another window since typing.begin",
+            ));
+        }
+
+        let injection = self.listener.lock().unwrap().injection.clone();
+        let _scope = injection.begin();
+        let progress = self.progress(id, false);
+        let options = session.options.clone();
+        let report =
+            type_text_paced(self.backend()?, text, &options, progress).map_err(RpcError::failed)?;
+        let typed: String = text.chars().take(report.typed).collect();
+        session.typed(&typed);
+        if report.cancelled {
+            return Err(RpcError::failed("Typing was cancelled"));
+        }
+        Ok(())
+    }
+
+    // Keep a single backend alive for the whole session so typing does not
+    // pay the connection setup cost on every call.
+    fn backend(&mut self) -> Result<&mut dyn Backend, RpcError> {
+        if self.backend.is_none() {
+            self.backend = Some(backend::open(self.backend_kind).map_err(RpcError::failed)?);
+        }
+        Ok(self.backend.as_deref_mut().unwrap())
+    }
+
+    // Handles a request from the queue, returning the response to send
+    fn answer(&mut self, request: Request) -> Option<Value> {
+        let id = request.id.clone().unwrap_or(Value::Null);
+        self.cancels_before = request.cancels;
+        let result = self.handle(&id, &request.method, request.params.clone());
+        finish(&request, result)
+    }
+
+    fn handle(&mut self, id: &Value, method: &str, params: Value) -> Result<Value, RpcError> {
+        match method {
+            "write" => {
+                let params: WriteParams = parse_params(params)?;
+                let injection = self.listener.lock().unwrap().injection.clone();
+                let _scope = injection.begin();
+
+                let window = focus::get_focused_window().ok();
+                let progress = self.progress(id, params.progress);
+                let report = insert_text(
+                    self.backend()?,
+                    &params.text,
+                    params.strategy,
+                    &params.typing,
+                    progress,
+                )
+                .map_err(RpcError::failed)?;
+                self.history.record(Insertion {
+                    chars: report.typed,
+                    window,
+                    keys: false,
+                });
+                Ok(serde_json::to_value(report).unwrap())
+            }
+            "dictate" => {
+                let params: DictateParams = parse_params(params)?;
+                let commands = dictate::vocabulary(&self.vocabularies, &params.language);
+                let segments = dictate::interpret(&params.text, &commands)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+                let injection = self.listener.lock().unwrap().injection.clone();
+                let _scope = injection.begin();
+
+                let window = focus::get_focused_window().ok();
+                let progress = self.progress(id, params.progress);
+                let report = dictate::execute(
+                    self.backend()?,
+                    &segments,
+                    params.strategy,
+                    &params.typing,
+                    progress,
+                )
+                .map_err(RpcError::failed)?;
+                self.history.record(Insertion {
+                    chars: report.typed,
+                    window,
+                    keys: segments
+                        .iter()
+                        .any(|segment| matches!(segment, dictate::Segment::Keys(_))),
+                });
+                Ok(serde_json::to_value(report).unwrap())
+            }
+            "undoLast" => {
+                let params: UndoLastParams = parse_params(params)?;
+                let window = focus::get_focused_window().ok();
+                let insertion = self
+                    .history
+                    .take_last(window.as_ref(), params.method)
+                    .map_err(|e| {
+                        let code = match e {
+                            UndoError::Empty => NOTHING_TO_UNDO,
+                            UndoError::UnknownWindow | UndoError::FocusChanged => FOCUS_CHANGED,
+                            UndoError::NotText => INVALID_PARAMS,
+                        };
+                        RpcError::new(code, e.to_string())
+                    })?;
+
+                let injection = self.listener.lock().unwrap().injection.clone();
+                let _scope = injection.begin();
+                history::undo(self.backend()?, &insertion, params.method).map_err(RpcError::failed)?;
+                Ok(json!({ "chars": insertion.chars, "method": params.method }))
+            }
+            "setVoiceCommands" => {
+                let params: SetVoiceCommandsParams = parse_params(params)?;
+                // Reject commands whose key sequences would fail on every use
+                for command in &params.commands {
+                    if let CommandAction::Keys(sequence) = &command.action {
+                        keyseq::parse(sequence).map_err(|e| {
+                            RpcError::new(INVALID_PARAMS, format!("{}: {}", command.phrase, e))
+                        })?;
+                    }
+                }
+                self.vocabularies.insert(params.language, params.commands);
+                Ok(Value::Null)
+            }
+            "keys" => {
+                let params: KeysParams = parse_params(params)?;
+                let actions = keyseq::parse(&params.sequence)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, e.to_string()))?;
+                let injection = self.listener.lock().unwrap().injection.clone();
+                let _scope = injection.begin();
+                keyseq::execute(self.backend()?, &actions).map_err(RpcError::failed)?;
+                Ok(Value::Null)
+            }
+            "typing.begin" => {
+                if self.session.is_some() {
+                    return Err(RpcError::failed("A typing session is already active"));
+                }
+                let params: TypingBeginParams = parse_params(params)?;
+                let window = focus::get_focused_window().ok();
+                self.session = Some(TypingSession::new(params.typing, window));
+                Ok(Value::Null)
+            }
+            "typing.append" => {
+                let params: TypingAppendParams = parse_params(params)?;
+                let mut session = self.take_session()?;
+                let text = session.prepare(&params.delta);
+                let result = self.type_in_session(id, &mut session, &text);
+                let typed = session.chars();
+                self.session = Some(session);
+                result?;
+                Ok(json!({ "typed": typed }))
+            }
+            "typing.end" => {
+                let mut session = self.take_session()?;
+                let text = session.flush();
+                let result = self.type_in_session(id, &mut session, &text);
+                let typed = session.chars();
+                self.history.record(session.into_insertion());
+                result?;
+                Ok(json!({ "typed": typed }))
+            }
+            "typing.abort" => {
+                let session = self.take_session()?;
+                let window = focus::get_focused_window().ok();
+                if session.focus_changed(window.as_ref()) {
+                    return Err(RpcError::new(
+                        FOCUS_CHANGED,
+                        "Focus moved to another window, the typed text was left in place",
+                    ));
+                }
+
+                let insertion = session.into_insertion();
+                let injection = self.listener.lock().unwrap().injection.clone();
+                let _scope = injection.begin();
+                history::undo(self.backend()?, &insertion, UndoMethod::Backspace)
+                    .map_err(RpcError::failed)?;
+                Ok(json!({ "removed": insertion.chars }))
+            }
+            "record.start" => {
+                if self.recording.as_ref().is_some_and(|recording| !recording.is_finished()) {
+                    return Err(RpcError::failed("A recording is already running"));
+                }
+                let params: RecordStartParams = parse_params(params)?;
+                if params
+                    .level_interval_ms
+                    .is_some_and(|ms| ms < audio::MIN_LEVEL_INTERVAL_MS)
+                {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        format!(
+                            "level_interval_ms must be at least {}",
+                            audio::MIN_LEVEL_INTERVAL_MS
+                        ),
+                    ));
+                }
+                let source = match &self.preroll {
+                    Some(_) if !params.source.is_default() => {
+                        return Err(RpcError::new(
+                            INVALID_PARAMS,
+                            "Recordings use the pre-roll input while it runs, stop it to pick another",
+                        ));
+                    }
+                    Some(preroll) => preroll.source(params.start_time),
+                    None => params.source.spec(),
+                };
+                let options = RecordOptions {
+                    source,
+                    format: params
+                        .format
+                        .unwrap_or_else(|| AudioFormat::from_path(&params.path)),
+                    path: params.path,
+                    max_duration: params.max_duration_ms.map(Duration::from_millis),
+                    vad: params.vad,
+                    levels: params.levels.then(|| {
+                        Duration::from_millis(
+                            params
+                                .level_interval_ms
+                                .unwrap_or(audio::DEFAULT_LEVEL_INTERVAL_MS),
+                        )
+                    }),
+                    realtime: true,
+                };
+                // Ends without record.stop are announced, record.stop still
+                // returns the summary afterwards
+                let recording = audio::start(options, |event| match event {
+                    AudioEvent::Speech(event) => notify("vad", event),
+                    AudioEvent::Level(level) => notify("level", level),
+                    AudioEvent::Ended(Ok(summary)) => notify("recordEnded", summary),
+                    AudioEvent::Ended(Err(e)) => notify("recordError", json!({ "message": e })),
+                })
+                .map_err(RpcError::failed)?;
+                self.recording = Some(recording);
+                Ok(Value::Null)
+            }
+            "record.stop" => {
+                let recording = self
+                    .recording
+                    .take()
+                    .ok_or_else(|| RpcError::failed("No recording, call record.start first"))?;
+                let summary = recording.stop().map_err(RpcError::failed)?;
+                Ok(serde_json::to_value(summary).unwrap())
+            }
+            "preroll.start" => {
+                let params: PreRollStartParams = parse_params(params)?;
+                if params.seconds <= 0.0 || params.seconds > audio::MAX_PREROLL_SECONDS {
+                    return Err(RpcError::new(
+                        INVALID_PARAMS,
+                        format!(
+                            "seconds must be above 0 and at most {}",
+                            audio::MAX_PREROLL_SECONDS
+                        ),
+                    ));
+                }
+                // A running monitor would hold on to the device
+                self.preroll = None;
+                let monitor = Monitor::start(params.source.spec(), params.seconds, true, |e| {
+                    notify("prerollError", json!({ "message": e }))
+                })
+                .map_err(RpcError::failed)?;
+                self.preroll = Some(monitor);
+                Ok(Value::Null)
+            }
+            "preroll.stop" => {
+                self.preroll = None;
+                Ok(Value::Null)
+            }
+            "record.devices" => {
+                let names = audio::device_names().map_err(RpcError::failed)?;
+                Ok(json!(names))
+            }
+            "getFocus" => {
+                let window = focus::get_focused_window().map_err(RpcError::failed)?;
+                Ok(serde_json::to_value(window).unwrap())
+            }
+            "restoreFocus" => {
+                let window: FocusedWindow = parse_params(params)?;
+                focus::restore_focus(&window).map_err(RpcError::failed)?;
+                Ok(Value::Null)
+            }
+            "subscribe" => {
+                let params: SubscribeParams = parse_params(params)?;
+                let format = match params.format {
+                    Some(format) => format
+                        .parse()
+                        .map_err(|e: String| RpcError::new(INVALID_PARAMS, e))?,
+                    None => Format::default(),
+                };
+                {
+                    let mut listener = self.listener.lock().unwrap();
+                    listener.format = Some(format);
+                    listener.private = params.private;
+                    listener.release_on_focus_change = params.release_on_focus_change;
+                    listener.injected = params.injected;
+                    listener.mouse = params.mouse.then(|| {
+                        Duration::from_millis(
+                            params
+                                .mouse_move_interval_ms
+                                .unwrap_or(DEFAULT_MOUSE_MOVE_INTERVAL_MS),
+                        )
+                    });
+                    if let Some(ms) = params.stuck_key_timeout_ms {
+                        listener.keys.set_timeout(Duration::from_millis(ms));
+                    }
+                }
+                self.start_listener();
+                Ok(Value::Null)
+            }
+            "unsubscribe" => {
+                self.listener.lock().unwrap().format = None;
+                Ok(Value::Null)
+            }
+            "keyState" => {
+                let snapshot = self.listener.lock().unwrap().snapshot();
+                Ok(serde_json::to_value(snapshot).unwrap())
+            }
+            "resetKeyState" => {
+                let out = self.listener.lock().unwrap().reset(SystemTime::now());
+                for output in out {
+                    notify_output(output);
+                }
+                Ok(Value::Null)
+            }
+            "registerHotkeys" => {
+                let params: RegisterHotkeysParams = parse_params(params)?;
+                self.listener
+                    .lock()
+                    .unwrap()
+                    .hotkeys
+                    .set_bindings(&params.bindings)
+                    .map_err(|e| RpcError::new(INVALID_PARAMS, e))?;
+                self.start_listener();
+                Ok(Value::Null)
+            }
+            _ => Err(RpcError::new(
+                METHOD_NOT_FOUND,
+                format!("Method not found: {}", method),
+            )),
+        }
+    }
+
+    // The listener never returns while it is healthy, so it gets its own
+    // thread and is only started once; unsubscribing just mutes raw events.
+    fn start_listener(&mut self) {
+        if self.listener_started {
+            return;
+        }
+        self.listener_started = true;
+
+        let listener = self.listener.clone();
+        let kind = self.listener_kind;
+        std::thread::spawn(move || {
+            if let Err(error) = listener::run(listener, kind, notify_output) {
+                notify("listenerError", json!({ "message": error.to_string() }));
+            }
+        });
+    }
+}
+
+fn parse_request(line: &str) -> Result<Request, (Value, RpcError)> {
+    let value: Value = serde_json::from_str(line)
+        .map_err(|e| (Value::Null, RpcError::new(PARSE_ERROR, e.to_string())))?;
+    let request: Request = serde_json::from_value(value)
+        .map_err(|e| (Value::Null, RpcError::new(INVALID_REQUEST, e.to_string())))?;
+
+    if request.jsonrpc.as_deref() != Some("2.0") {
+        return Err((
+            request.id.unwrap_or(Value::Null),
+            RpcError::new(INVALID_REQUEST, "jsonrpc must be \"2.0\""),
+        ));
+    }
+    Ok(request)
+}
+
+// The response to send, if any
+fn finish(request: &Request, result: Result<Value, RpcError>) -> Option<Value> {
+    // Requests without an id are notifications and never get a response.
+    if let Some(id) = &request.id {
+        return Some(response(id.clone(), result));
+    }
+    if let Err(error) = result {
+        eprintln!("{} failed: {}", request.method, error.message);
+    }
+    None
+}
+
+// Queues the requests read from `input` for the main thread, except
+// cancelWrite, which takes effect right away. Responses the reader gives
+// itself, to cancelWrite and to lines that aren't requests, go to `reply`.
+fn read_requests(
+    input: impl BufRead,
+    cancels: &AtomicU64,
+    queue: &mpsc::Sender<Request>,
+    mut reply: impl FnMut(Value),
+) -> std::io::Result<()> {
+    for line in input.lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        match parse_request(&line) {
+            Ok(request) if request.method == "cancelWrite" => {
+                cancels.fetch_add(1, Ordering::SeqCst);
+                if let Some(message) = finish(&request, Ok(Value::Null)) {
+                    reply(message);
+                }
+            }
+            Ok(mut request) => {
+                request.cancels = cancels.load(Ordering::SeqCst);
+                if queue.send(request).is_err() {
+                    break;
+                }
+            }
+            Err((id, error)) => reply(response(id, Err(error))),
+        }
+    }
+    Ok(())
+}
+
+
+pub fn serve(
+    backend_kind: Option<BackendKind>,
+    listener_kind: Option<CaptureKind>,
+    grab: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cancels = Arc::new(AtomicU64::new(0));
+    let mut daemon = Daemon::new(cancels.clone(), backend_kind, listener_kind, grab);
+
+    // Requests are read on their own thread so `cancelWrite` can interrupt a
+    // write that keeps the main thread busy. Everything else runs in order on
+    // the main thread, which owns the input backend.
+    let (tx, rx) = mpsc::channel::<Request>();
+    let reader = std::thread::spawn(move || {
+        read_requests(std::io::stdin().lock(), &cancels, &tx, |message| {
+            println!("{}", message)
+        })
+    });
+
+    for request in rx {
+        if let Some(message) = daemon.answer(request) {
+            println!("{}", message);
+        }
+    }
+
+    reader.join().unwrap()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotkey::HotkeyEvent;
+    use enigo::{Direction, InputResult, Key, Keyboard};
+
+    // Types into a string instead of the session
+    struct NullBackend(Arc<Mutex<String>>);
+
+    impl Keyboard for NullBackend {
+        fn fast_text(&mut self, text: &str) -> InputResult<Option<()>> {
+            self.0.lock().unwrap().push_str(text);
+            Ok(Some(()))
+        }
+
+        fn key(&mut self, _: Key, _: Direction) -> InputResult<()> {
+            Ok(())
+        }
+
+        fn raw(&mut self, _: u16, _: Direction) -> InputResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Backend for NullBackend {
+        fn name(&self) -> &'static str {
+            "null"
+        }
+    }
+
+    fn daemon() -> Daemon {
+        Daemon::new(Arc::new(AtomicU64::new(0)), None, None, false)
+    }
+
+    // A daemon typing into the returned string
+    fn typing_daemon() -> (Daemon, Arc<Mutex<String>>) {
+        let typed = Arc::new(Mutex::new(String::new()));
+        let mut daemon = daemon();
+        daemon.backend = Some(Box::new(NullBackend(typed.clone())));
+        (daemon, typed)
+    }
+
+    fn call(daemon: &mut Daemon, method: &str, params: Value) -> Result<Value, RpcError> {
+        daemon.handle(&json!(1), method, params)
+    }
+
+    // Reads all of `input` like the stdin thread, then answers the queued
+    // requests. The reader's own replies come first.
+    fn serve_lines(daemon: &mut Daemon, input: &str) -> Vec<Value> {
+        let (tx, rx) = mpsc::channel();
+        let mut out = Vec::new();
+        read_requests(input.as_bytes(), &daemon.cancels.clone(), &tx, |message| {
+            out.push(message)
+        })
+        .unwrap();
+        drop(tx);
+        out.extend(rx.into_iter().filter_map(|request| daemon.answer(request)));
+        out
+    }
+
+    fn error_code(message: &Value) -> Option<i64> {
+        message["error"]["code"].as_i64()
+    }
+
+    #[test]
+    fn answers_lines_that_are_no_requests() {
+        let mut daemon = daemon();
+        let out = serve_lines(
+            &mut daemon,
+            concat!(
+                "not json\n",
+                "\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":1}\n",
+                "{\"jsonrpc\":\"1.0\",\"id\":2,\"method\":\"keyState\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"noSuchMethod\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"method\":\"noSuchMethod\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":4,\"method\":\"keyState\"}\n",
+            ),
+        );
+        assert_eq!(out.len(), 5, "{:?}", out);
+        assert_eq!((error_code(&out[0]), &out[0]["id"]), (Some(PARSE_ERROR), &Value::Null));
+        assert_eq!((error_code(&out[1]), &out[1]["id"]), (Some(INVALID_REQUEST), &Value::Null));
+        assert_eq!((error_code(&out[2]), &out[2]["id"]), (Some(INVALID_REQUEST), &json!(2)));
+        // The notification to an unknown method gets no response at all
+        assert_eq!((error_code(&out[3]), &out[3]["id"]), (Some(METHOD_NOT_FOUND), &json!(3)));
+        assert_eq!(out[4]["id"], 4);
+        assert_eq!(out[4]["result"]["keys"], json!([]));
+        assert!(out.iter().all(|message| message["jsonrpc"] == "2.0"));
+    }
+
+    #[test]
+    fn rejects_invalid_params() {
+        let (mut daemon, typed) = typing_daemon();
+        let invalid = [
+            ("write", json!({})),
+            ("write", json!({ "text": "a", "strategy": "shout" })),
+            ("write", json!({ "text": "a", "typing": { "chars_per_second": 1e-300 } })),
+            ("keys", json!({ "sequence": "{Ctrl+Foo}" })),
+            ("keys", json!({ "sequence": "{Sleep 4000000000}" })),
+            (
+                "setVoiceCommands",
+                json!({ "language": "en", "commands": [{ "phrase": "x", "action": { "keys": "{Nope}" } }] }),
+            ),
+            ("registerHotkeys", json!({ "bindings": "ctrl" })),
+            ("record.start", json!({ "path": "x.wav", "null": true, "level_interval_ms": 0 })),
+            ("preroll.start", json!({ "null": true, "seconds": -1 })),
+        ];
+        for (method, params) in invalid {
+            let error = call(&mut daemon, method, params.clone()).unwrap_err();
+            assert_eq!(error.code, INVALID_PARAMS, "{} {}: {}", method, params, error.message);
+        }
+        assert_eq!(typed.lock().unwrap().as_str(), "");
+
+        let error = call(&mut daemon, "record.stop", Value::Null).unwrap_err();
+        assert_eq!(error.code, COMMAND_FAILED);
+        let error = call(&mut daemon, "typing.end", Value::Null).unwrap_err();
+        assert_eq!(error.code, COMMAND_FAILED);
+    }
+
+    #[test]
+    fn writes_through_the_backend() {
+        let (mut daemon, typed) = typing_daemon();
+        let report = call(&mut daemon, "write", json!({ "text": "hello" })).unwrap();
+        assert_eq!(report, json!({ "typed": 5, "total": 5, "cancelled": false }));
+        call(&mut daemon, "keys", json!({ "sequence": "x{Enter}" })).unwrap();
+        assert_eq!(typed.lock().unwrap().as_str(), "hellox");
+    }
+
+    #[test]
+    fn cancel_write_stops_writes_sent_before_it() {
+        let (mut daemon, typed) = typing_daemon();
+        // Everything is read before the first write runs, so the cancel
+        // arrives while the first write is still queued
+        let out = serve_lines(
+            &mut daemon,
+            concat!(
+                "{\"jsonrpc\":\"2.0\",\"id\":1,\"method\":\"write\",\"params\":{\"text\":\"abc\",\"typing\":{\"chunk_size\":1}}}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":2,\"method\":\"cancelWrite\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"method\":\"cancelWrite\"}\n",
+                "{\"jsonrpc\":\"2.0\",\"id\":3,\"method\":\"write\",\"params\":{\"text\":\"de\"}}\n",
+            ),
+        );
+        assert_eq!(out.len(), 3, "{:?}", out);
+        assert_eq!(out[0], json!({ "jsonrpc": "2.0", "id": 2, "result": null }));
+        assert_eq!(out[1]["id"], 1);
+        assert_eq!(out[1]["result"], json!({ "typed": 1, "total": 3, "cancelled": true }));
+        assert_eq!(out[2]["id"], 3);
+        assert_eq!(out[2]["result"], json!({ "typed": 2, "total": 2, "cancelled": false }));
+        assert_eq!(typed.lock().unwrap().as_str(), "ade");
+    }
+
+    #[test]
+    fn limits_the_pre_roll() {
+        let mut daemon = daemon();
+        for seconds in [0.0, 30.5, 1e308] {
+            let error = call(&mut daemon, "preroll.start", json!({ "null": true, "seconds": seconds }))
+                .unwrap_err();
+            assert_eq!(error.code, INVALID_PARAMS, "{}", seconds);
+        }
+        assert!(daemon.preroll.is_none());
+    }
+
+    #[test]
+    fn records_from_the_pressed_time_of_a_hold() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut daemon = daemon();
+        call(&mut daemon, "preroll.start", json!({ "null": true, "seconds": 1 })).unwrap();
+        std::thread::sleep(Duration::from_millis(500));
+
+        // The hotkey notification's pressed time, as a client gets it
+        let hold = HotkeyEvent::HoldStart {
+            id: "record".to_string(),
+            time: SystemTime::now(),
+            pressed: SystemTime::now() - Duration::from_millis(300),
+        };
+        let pressed = serde_json::to_value(&hold).unwrap()["pressed"].clone();
+        let path = dir.path().join("held.wav");
+        call(
+            &mut daemon,
+            "record.start",
+            json!({ "path": path, "start_time": pressed }),
+        )
+        .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        let summary = call(&mut daemon, "record.stop", Value::Null).unwrap();
+        let duration_ms = summary["duration_ms"].as_u64().unwrap();
+        assert!((400..=700).contains(&duration_ms), "{}", duration_ms);
+    }
+}
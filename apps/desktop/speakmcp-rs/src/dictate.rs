@@ -0,0 +1,321 @@
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use crate::backend::{self, Backend, BackendKind};
+use crate::keyseq::{self, Action};
+use crate::writer::{insert_text, Strategy, TypingOptions, TypingReport};
+
+pub const DEFAULT_LANGUAGE: &str = "en";
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum CommandAction {
+    // Inserted verbatim, without the space that separates dictated words
+    Text(String),
+    // A key sequence in the `keys` command syntax
+    Keys(String),
+    // Capitalize the first letter of the next dictated word
+    CapNext,
+}
+
+#[derive(Deserialize, Debug, Clone, PartialEq)]
+pub struct VoiceCommand {
+    pub phrase: String,
+    pub action: CommandAction,
+}
+
+// Voice commands per language, keyed by language code, e.g.
+// {"en": [{"phrase": "new line", "action": {"text": "\n"}}]}
+pub type Vocabularies = HashMap<String, Vec<VoiceCommand>>;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    Text(String),
+    Keys(Vec<Action>),
+}
+
+fn command(phrase: &str, action: CommandAction) -> VoiceCommand {
+    VoiceCommand {
+        phrase: phrase.to_string(),
+        action,
+    }
+}
+
+fn word_modifier() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "Alt"
+    } else {
+        "Ctrl"
+    }
+}
+
+// "en" for "en-US" and "en_GB"
+fn base_language(language: &str) -> &str {
+    language.split(['-', '_']).next().unwrap_or_default()
+}
+
+pub fn builtin(language: &str) -> Vec<VoiceCommand> {
+    let select_all = CommandAction::Keys(format!("{{{}+a}}", keyseq::primary_modifier()));
+    let delete_word = CommandAction::Keys(format!("{{{}+Backspace}}", word_modifier()));
+
+    // Matched on the base language so "en-US" gets the English commands
+    match base_language(language) {
+        "en" => vec![
+            command("new line", CommandAction::Text("\n".to_string())),
+            command("new paragraph", CommandAction::Text("\n\n".to_string())),
+            command("delete last word", delete_word),
+            command("select all", select_all),
+            command("cap next", CommandAction::CapNext),
+        ],
+        "de" => vec![
+            command("neue zeile", CommandAction::Text("\n".to_string())),
+            command("neuer absatz", CommandAction::Text("\n\n".to_string())),
+            command("letztes wort löschen", delete_word),
+            command("alles auswählen", select_all),
+            command("nächstes groß", CommandAction::CapNext),
+        ],
+        _ => Vec::new(),
+    }
+}
+
+pub fn load_vocabularies(path: &str) -> Result<Vocabularies, Box<dyn std::error::Error>> {
+    let file = std::fs::read_to_string(path)
+        .map_err(|e| format!("Failed to read voice commands from {}: {}", path, e))?;
+    let vocabularies: Vocabularies = serde_json::from_str(&file)
+        .map_err(|e| format!("Invalid voice commands in {}: {}", path, e))?;
+    Ok(vocabularies)
+}
+
+// Configured commands replace the built in ones for their language. Like
+// the built in ones, commands for "en" cover "en-US" unless it has its own.
+pub fn vocabulary(vocabularies: &Vocabularies, language: &str) -> Vec<VoiceCommand> {
+    vocabularies
+        .get(language)
+        .or_else(|| vocabularies.get(base_language(language)))
+        .cloned()
+        .unwrap_or_else(|| builtin(language))
+}
+
+// Transcribers add punctuation and capitalization, so "New line." should
+// still match the "new line" command.
+fn normalize(word: &str) -> String {
+    word.trim_matches(|c: char| !c.is_alphanumeric()).to_lowercase()
+}
+
+fn capitalize(word: &str) -> String {
+    let mut chars = word.chars();
+    match chars.next() {
+        Some(first) => first.to_uppercase().chain(chars).collect(),
+        None => String::new(),
+    }
+}
+
+struct Builder {
+    segments: Vec<Segment>,
+    text: String,
+    // Whether the next dictated word needs a separating space
+    spaced: bool,
+    cap_next: bool,
+}
+
+impl Builder {
+    fn word(&mut self, word: &str) {
+        if self.spaced {
+            self.text.push(' ');
+        }
+        if std::mem::take(&mut self.cap_next) {
+            self.text.push_str(&capitalize(word));
+        } else {
+            self.text.push_str(word);
+        }
+        self.spaced = true;
+    }
+
+    fn action(&mut self, action: &CommandAction) -> Result<(), keyseq::ParseError> {
+        match action {
+            CommandAction::Text(text) => {
+                self.text.push_str(text);
+                self.spaced = !text.ends_with(char::is_whitespace);
+            }
+            CommandAction::Keys(sequence) => {
+                let actions = keyseq::parse(sequence)?;
+                if !self.text.is_empty() {
+                    self.segments.push(Segment::Text(std::mem::take(&mut self.text)));
+                }
+                self.segments.push(Segment::Keys(actions));
+                // Whatever the keys did to the text around the cursor, we
+                // can't know whether a space is needed
+                self.spaced = false;
+            }
+            CommandAction::CapNext => self.cap_next = true,
+        }
+        Ok(())
+    }
+}
+
+// Splits a transcript into text to type and key actions, preferring the
+// longest command phrase at every word.
+pub fn interpret(transcript: &str, commands: &[VoiceCommand]) -> Result<Vec<Segment>, keyseq::ParseError> {
+    let phrases: Vec<(Vec<String>, &CommandAction)> = commands
+        .iter()
+        .map(|c| (c.phrase.split_whitespace().map(normalize).collect(), &c.action))
+        .filter(|(words, _): &(Vec<String>, _)| !words.is_empty())
+        .collect();
+
+    let words: Vec<&str> = transcript.split_whitespace().collect();
+    let normalized: Vec<String> = words.iter().map(|w| normalize(w)).collect();
+    let mut builder = Builder {
+        segments: Vec::new(),
+        text: String::new(),
+        spaced: false,
+        cap_next: false,
+    };
+
+    let mut i = 0;
+    while i < words.len() {
+        let matched = phrases
+            .iter()
+            .filter(|(phrase, _)| normalized[i..].starts_with(phrase))
+            .max_by_key(|(phrase, _)| phrase.len());
+        match matched {
+            Some((phrase, action)) => {
+                builder.action(action)?;
+                i += phrase.len();
+            }
+            None => {
+                builder.word(words[i]);
+                i += 1;
+            }
+        }
+    }
+
+    if !builder.text.is_empty() {
+        builder.segments.push(Segment::Text(builder.text));
+    }
+    Ok(builder.segments)
+}
+
+// Runs the segments through one backend session. Progress covers the text
+// segments only and stops everything once it returns false.
+pub fn execute(
+    backend: &mut dyn Backend,
+    segments: &[Segment],
+    strategy: Strategy,
+    options: &TypingOptions,
+    mut progress: impl FnMut(usize, usize) -> bool,
+) -> Result<TypingReport, Box<dyn std::error::Error>> {
+    let total = segments
+        .iter()
+        .map(|segment| match segment {
+            Segment::Text(text) => text.chars().count(),
+            Segment::Keys(_) => 0,
+        })
+        .sum();
+    let mut report = TypingReport {
+        typed: 0,
+        total,
+        cancelled: false,
+    };
+
+    for segment in segments {
+        match segment {
+            Segment::Text(text) => {
+                let offset = report.typed;
+                let typed = insert_text(backend, text, strategy, options, |typed, _| {
+                    progress(offset + typed, total)
+                })?;
+                report.typed += typed.typed;
+                if typed.cancelled {
+                    report.cancelled = true;
+                    break;
+                }
+            }
+            Segment::Keys(actions) => {
+                if !progress(report.typed, total) {
+                    report.cancelled = true;
+                    break;
+                }
+                keyseq::execute(backend, actions)?;
+            }
+        }
+    }
+    Ok(report)
+}
+
+pub fn dictate_text(
+    transcript: &str,
+    commands: &[VoiceCommand],
+    strategy: Strategy,
+    options: &TypingOptions,
+    backend: Option<BackendKind>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let segments = interpret(transcript, commands)?;
+    let mut backend = backend::open(backend)?;
+    execute(backend.as_mut(), &segments, strategy, options, |_, _| true)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enigo::{Direction, Key};
+
+    fn text(segments: &[Segment]) -> String {
+        segments
+            .iter()
+            .map(|segment| match segment {
+                Segment::Text(text) => text.clone(),
+                Segment::Keys(_) => "<keys>".to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interprets_formatting_commands() {
+        let commands = builtin("en-US");
+        let segments = interpret("Dear team, New line. thanks cap next bob", &commands).unwrap();
+        assert_eq!(text(&segments), "Dear team,\nthanks Bob");
+
+        let segments = interpret("hello world delete last word there", &commands).unwrap();
+        assert_eq!(text(&segments), "hello world<keys>there");
+        assert!(matches!(
+            &segments[1],
+            Segment::Keys(actions) if actions.contains(&Action::Key(Key::Backspace, Direction::Click))
+        ));
+    }
+
+    #[test]
+    fn configured_commands_replace_builtins_per_language() {
+        let vocabularies: Vocabularies = serde_json::from_str(
+            r#"{"en": [
+                {"phrase": "new line", "action": {"text": " // "}},
+                {"phrase": "new line please", "action": {"keys": "{Enter}"}}
+            ]}"#,
+        )
+        .unwrap();
+        let commands = vocabulary(&vocabularies, "en");
+        assert_eq!(text(&interpret("a new line b", &commands).unwrap()), "a // b");
+        assert_eq!(
+            text(&interpret("a new line please b", &commands).unwrap()),
+            "a<keys>b"
+        );
+
+        // Regional variants get the base language's configuration
+        let commands = vocabulary(&vocabularies, "en-US");
+        assert_eq!(text(&interpret("a new line b", &commands).unwrap()), "a // b");
+        let mut regional = vocabularies.clone();
+        regional.insert(
+            "en-US".to_string(),
+            vec![command("new line", CommandAction::Text(" | ".to_string()))],
+        );
+        let commands = vocabulary(&regional, "en-US");
+        assert_eq!(text(&interpret("a new line b", &commands).unwrap()), "a | b");
+        let commands = vocabulary(&regional, "en_GB");
+        assert_eq!(text(&interpret("a new line b", &commands).unwrap()), "a // b");
+
+        let commands = vocabulary(&vocabularies, "de");
+        assert_eq!(text(&interpret("hallo neue zeile welt", &commands).unwrap()), "hallo\nwelt");
+        assert!(vocabulary(&vocabularies, "fr").is_empty());
+    }
+}
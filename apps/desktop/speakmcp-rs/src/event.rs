@@ -0,0 +1,387 @@
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use rdev::{Button, Event, EventType, Key};
+use serde::Serialize;
+use serde_json::{json, Value};
+
+use crate::layout::KeyInfo;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Format {
+    // Legacy shape where `data` is a JSON encoded string
+    #[default]
+    V1,
+    // Typed shape, see `EventV2`
+    V2,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "v1" => Ok(Format::V1),
+            "v2" => Ok(Format::V2),
+            _ => Err(format!("Unknown event format: {} (expected v1 or v2)", s)),
+        }
+    }
+}
+
+#[derive(Serialize)]
+pub struct RdevEvent {
+    event_type: String,
+    name: Option<String>,
+    time: SystemTime,
+    data: String,
+}
+
+pub fn deal_event_to_json(event: Event) -> RdevEvent {
+    let mut jsonify_event = RdevEvent {
+        event_type: "".to_string(),
+        name: event.name,
+        time: event.time,
+        data: "".to_string(),
+    };
+    match event.event_type {
+        EventType::KeyPress(key) => {
+            jsonify_event.event_type = "KeyPress".to_string();
+            jsonify_event.data = json!({
+                "key": format!("{:?}", key)
+            })
+            .to_string();
+        }
+        EventType::KeyRelease(key) => {
+            jsonify_event.event_type = "KeyRelease".to_string();
+            jsonify_event.data = json!({
+                "key": format!("{:?}", key)
+            })
+            .to_string();
+        }
+        EventType::MouseMove { x, y } => {
+            jsonify_event.event_type = "MouseMove".to_string();
+            jsonify_event.data = json!({
+                "x": x,
+                "y": y
+            })
+            .to_string();
+        }
+        EventType::ButtonPress(key) => {
+            jsonify_event.event_type = "ButtonPress".to_string();
+            jsonify_event.data = json!({
+                "key": format!("{:?}", key)
+            })
+            .to_string();
+        }
+        EventType::ButtonRelease(key) => {
+            jsonify_event.event_type = "ButtonRelease".to_string();
+            jsonify_event.data = json!({
+                "key": format!("{:?}", key)
+            })
+            .to_string();
+        }
+        EventType::Wheel { delta_x, delta_y } => {
+            jsonify_event.event_type = "Wheel".to_string();
+            jsonify_event.data = json!({
+                "delta_x": delta_x,
+                "delta_y": delta_y
+            })
+            .to_string();
+        }
+    }
+
+    jsonify_event
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event_type")]
+pub enum EventData {
+    KeyPress { key: String },
+    KeyRelease { key: String },
+    ButtonPress { button: String },
+    ButtonRelease { button: String },
+    MouseMove { x: f64, y: f64 },
+    Wheel { delta_x: i64, delta_y: i64 },
+}
+
+impl From<EventType> for EventData {
+    fn from(event_type: EventType) -> Self {
+        match event_type {
+            EventType::KeyPress(key) => EventData::KeyPress {
+                key: format!("{:?}", key),
+            },
+            EventType::KeyRelease(key) => EventData::KeyRelease {
+                key: format!("{:?}", key),
+            },
+            EventType::ButtonPress(button) => EventData::ButtonPress {
+                button: format!("{:?}", button),
+            },
+            EventType::ButtonRelease(button) => EventData::ButtonRelease {
+                button: format!("{:?}", button),
+            },
+            EventType::MouseMove { x, y } => EventData::MouseMove { x, y },
+            EventType::Wheel { delta_x, delta_y } => EventData::Wheel { delta_x, delta_y },
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct EventV2 {
+    pub version: u8,
+    pub name: Option<String>,
+    pub time: SystemTime,
+    #[serde(flatten)]
+    pub data: EventData,
+    // Set for key events
+    #[serde(flatten)]
+    pub key_info: Option<KeyInfo>,
+}
+
+impl From<Event> for EventV2 {
+    fn from(event: Event) -> Self {
+        EventV2 {
+            version: 2,
+            name: event.name,
+            time: event.time,
+            data: event.event_type.into(),
+            key_info: None,
+        }
+    }
+}
+
+// Stands in for the real key name of keys that are not part of a registered
+// shortcut when the listener runs in private mode.
+pub const OTHER_KEY: &str = "Other";
+
+pub fn other_key_to_value(event: &Event, format: Format) -> Option<serde_json::Value> {
+    let data = match event.event_type {
+        EventType::KeyPress(_) => EventData::KeyPress {
+            key: OTHER_KEY.to_string(),
+        },
+        EventType::KeyRelease(_) => EventData::KeyRelease {
+            key: OTHER_KEY.to_string(),
+        },
+        _ => return None,
+    };
+    let value = match format {
+        Format::V1 => {
+            let event_type = match data {
+                EventData::KeyPress { .. } => "KeyPress",
+                _ => "KeyRelease",
+            };
+            serde_json::to_value(RdevEvent {
+                event_type: event_type.to_string(),
+                name: None,
+                time: event.time,
+                data: json!({ "key": OTHER_KEY }).to_string(),
+            })
+        }
+        Format::V2 => serde_json::to_value(EventV2 {
+            version: 2,
+            name: None,
+            time: event.time,
+            data,
+            key_info: None,
+        }),
+    };
+    Some(value.unwrap())
+}
+
+// `key_info` adds the evdev code, keycode and layout character of key events,
+// next to `key` in both formats.
+pub fn event_to_value(
+    event: Event,
+    format: Format,
+    key_info: Option<KeyInfo>,
+) -> serde_json::Value {
+    match format {
+        Format::V1 => {
+            let mut event = deal_event_to_json(event);
+            if let Some(key_info) = key_info {
+                let mut data: serde_json::Value = serde_json::from_str(&event.data).unwrap();
+                let info = serde_json::to_value(key_info).unwrap();
+                data.as_object_mut()
+                    .unwrap()
+                    .extend(info.as_object().unwrap().clone());
+                event.data = data.to_string();
+            }
+            serde_json::to_value(event).unwrap()
+        }
+        Format::V2 => serde_json::to_value(EventV2 {
+            key_info,
+            ..EventV2::from(event)
+        })
+        .unwrap(),
+    }
+}
+
+// Debug names as rdev prints them, "KeyA" or "Unknown(134)". Keys hidden
+// by private mode come back as a key no shortcut uses.
+fn parse_key(name: &str) -> Option<Key> {
+    if name == OTHER_KEY {
+        return Some(Key::Unknown(0));
+    }
+    match name.strip_prefix("Unknown(").and_then(|code| code.strip_suffix(')')) {
+        Some(code) => code.parse().ok().map(Key::Unknown),
+        None => serde_json::from_value(Value::String(name.to_string())).ok(),
+    }
+}
+
+fn parse_button(name: &str) -> Option<Button> {
+    match name.strip_prefix("Unknown(").and_then(|code| code.strip_suffix(')')) {
+        Some(code) => code.parse().ok().map(Button::Unknown),
+        None => serde_json::from_value(Value::String(name.to_string())).ok(),
+    }
+}
+
+// The rdev event behind a line of `listen` output in either format, None
+// for hotkey and key state lines.
+pub fn value_to_event(value: &Value) -> Option<Event> {
+    let time = serde_json::from_value(value.get("time")?.clone()).ok()?;
+    let name = value.get("name").and_then(Value::as_str).map(str::to_string);
+    let data = match value.get("data").and_then(Value::as_str) {
+        Some(data) => serde_json::from_str(data).ok()?,
+        None => value.clone(),
+    };
+    let text = |field| data.get(field).and_then(Value::as_str);
+    let int = |field| data.get(field).and_then(Value::as_i64);
+    let float = |field| data.get(field).and_then(Value::as_f64);
+    // v1 calls buttons "key" too
+    let button = || text("button").or_else(|| text("key")).and_then(parse_button);
+
+    let event_type = match value.get("event_type")?.as_str()? {
+        "KeyPress" => EventType::KeyPress(parse_key(text("key")?)?),
+        "KeyRelease" => EventType::KeyRelease(parse_key(text("key")?)?),
+        "ButtonPress" => EventType::ButtonPress(button()?),
+        "ButtonRelease" => EventType::ButtonRelease(button()?),
+        "MouseMove" => EventType::MouseMove {
+            x: float("x")?,
+            y: float("y")?,
+        },
+        "Wheel" => EventType::Wheel {
+            delta_x: int("delta_x")?,
+            delta_y: int("delta_y")?,
+        },
+        _ => return None,
+    };
+    Some(Event {
+        time,
+        name,
+        event_type,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn event(event_type: EventType) -> Event {
+        Event {
+            time: SystemTime::UNIX_EPOCH,
+            name: None,
+            event_type,
+        }
+    }
+
+    #[test]
+    fn v1_keeps_data_as_encoded_string() {
+        let value = event_to_value(event(EventType::KeyPress(Key::KeyA)), Format::V1, None);
+        assert_eq!(value["event_type"], "KeyPress");
+        assert_eq!(value["data"], r#"{"key":"KeyA"}"#);
+    }
+
+    #[test]
+    fn other_key_hides_name_and_key() {
+        let mut typed = event(EventType::KeyPress(Key::KeyP));
+        typed.name = Some("p".to_string());
+        let value = other_key_to_value(&typed, Format::V2).unwrap();
+        assert_eq!(value["key"], OTHER_KEY);
+        assert!(value["name"].is_null());
+        assert!(!value.to_string().contains("KeyP\""));
+    }
+
+    #[test]
+    fn v2_uses_structured_fields() {
+        let value = event_to_value(
+            event(EventType::KeyRelease(Key::ControlLeft)),
+            Format::V2,
+            None,
+        );
+        assert_eq!(value["version"], 2);
+        assert_eq!(value["event_type"], "KeyRelease");
+        assert_eq!(value["key"], "ControlLeft");
+
+        let value = event_to_value(
+            event(EventType::ButtonPress(Button::Unknown(8))),
+            Format::V2,
+            None,
+        );
+        assert_eq!(value["button"], "Unknown(8)");
+
+        let value = event_to_value(
+            event(EventType::Wheel {
+                delta_x: 0,
+                delta_y: -1,
+            }),
+            Format::V2,
+            None,
+        );
+        assert_eq!(value["delta_y"], -1);
+    }
+
+    #[test]
+    fn key_info_sits_next_to_the_key() {
+        let key_info = KeyInfo {
+            evdev_code: Some(16),
+            keycode: Some(24),
+            char: Some('a'),
+        };
+        let press = || event(EventType::KeyPress(Key::KeyQ));
+
+        let value = event_to_value(press(), Format::V1, Some(key_info));
+        assert_eq!(
+            value["data"],
+            r#"{"char":"a","evdev_code":16,"key":"KeyQ","keycode":24}"#
+        );
+        let value = event_to_value(press(), Format::V2, Some(key_info));
+        assert_eq!(value["key"], "KeyQ");
+        assert_eq!(value["evdev_code"], 16);
+        assert_eq!(value["char"], "a");
+
+        let value = event_to_value(press(), Format::V2, None);
+        assert!(value.get("evdev_code").is_none());
+    }
+
+    #[test]
+    fn parses_its_own_output_back() {
+        let events = [
+            EventType::KeyPress(Key::KeyA),
+            EventType::KeyRelease(Key::Unknown(134)),
+            EventType::ButtonPress(Button::Unknown(8)),
+            EventType::ButtonRelease(Button::Left),
+            EventType::MouseMove { x: 1.5, y: 2.0 },
+            EventType::Wheel {
+                delta_x: 0,
+                delta_y: -1,
+            },
+        ];
+        for format in [Format::V1, Format::V2] {
+            for event_type in events {
+                let mut typed = event(event_type);
+                typed.name = Some("a".to_string());
+                let value = event_to_value(typed, format, None);
+                let parsed = value_to_event(&value).unwrap();
+                assert_eq!(parsed.event_type, event_type);
+                assert_eq!(parsed.name.as_deref(), Some("a"));
+                assert_eq!(parsed.time, SystemTime::UNIX_EPOCH);
+            }
+        }
+
+        let hidden = other_key_to_value(&event(EventType::KeyPress(Key::KeyP)), Format::V1);
+        assert_eq!(
+            value_to_event(&hidden.unwrap()).unwrap().event_type,
+            EventType::KeyPress(Key::Unknown(0))
+        );
+        assert!(value_to_event(&json!({"event_type": "HotkeyToggled"})).is_none());
+    }
+}
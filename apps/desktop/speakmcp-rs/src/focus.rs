@@ -0,0 +1,274 @@
+use serde::{Deserialize, Serialize};
+
+/// Describes the window that had keyboard focus, as printed by `get-focus`
+/// and accepted back by `restore-focus`.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct FocusedWindow {
+    pub window_id: u64,
+    pub pid: Option<u32>,
+    pub process_name: Option<String>,
+    pub title: Option<String>,
+}
+
+pub fn get_focused_window() -> Result<FocusedWindow, Box<dyn std::error::Error>> {
+    backend::get_focused_window()
+}
+
+pub fn restore_focus(window: &FocusedWindow) -> Result<(), Box<dyn std::error::Error>> {
+    backend::restore_focus(window)
+}
+
+// The kernel's name for the process, cut off at 15 characters
+#[cfg(target_os = "linux")]
+pub(crate) fn process_name(pid: u32) -> Option<String> {
+    std::fs::read_to_string(format!("/proc/{}/comm", pid))
+        .ok()
+        .map(|name| name.trim().to_string())
+        .filter(|name| !name.is_empty())
+}
+
+#[cfg(target_os = "linux")]
+mod backend {
+    use super::{process_name, FocusedWindow};
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{
+        AtomEnum, ClientMessageEvent, ConfigureWindowAux, ConnectionExt, EventMask, InputFocus,
+        StackMode, Window,
+    };
+    use x11rb::rust_connection::RustConnection;
+    use x11rb::wrapper::ConnectionExt as _;
+    use x11rb::CURRENT_TIME;
+
+    // Source indication 2 tells the window manager the request comes from a
+    // pager-like tool acting on behalf of the user, so focus stealing
+    // prevention does not ignore it.
+    const SOURCE_PAGER: u32 = 2;
+
+    struct X11 {
+        conn: RustConnection,
+        root: Window,
+    }
+
+    impl X11 {
+        fn connect() -> Result<Self, Box<dyn std::error::Error>> {
+            let (conn, screen_num) = x11rb::connect(None)
+                .map_err(|e| format!("Failed to connect to X11 display: {}", e))?;
+            let root = conn.setup().roots[screen_num].root;
+            Ok(X11 { conn, root })
+        }
+
+        fn atom(&self, name: &str) -> Result<u32, Box<dyn std::error::Error>> {
+            Ok(self.conn.intern_atom(false, name.as_bytes())?.reply()?.atom)
+        }
+
+        fn property32(
+            &self,
+            window: Window,
+            property: u32,
+            kind: AtomEnum,
+        ) -> Result<Vec<u32>, Box<dyn std::error::Error>> {
+            let reply = self
+                .conn
+                .get_property(false, window, property, kind, 0, u32::MAX / 4)?
+                .reply()?;
+            Ok(reply.value32().map(|v| v.collect()).unwrap_or_default())
+        }
+
+        fn property_string(
+            &self,
+            window: Window,
+            property: u32,
+        ) -> Result<Option<String>, Box<dyn std::error::Error>> {
+            let reply = self
+                .conn
+                .get_property(false, window, property, AtomEnum::ANY, 0, u32::MAX / 4)?
+                .reply()?;
+            if reply.value.is_empty() {
+                return Ok(None);
+            }
+            Ok(Some(String::from_utf8_lossy(&reply.value).into_owned()))
+        }
+
+        fn supports(&self, atom: u32) -> Result<bool, Box<dyn std::error::Error>> {
+            let supported = self.atom("_NET_SUPPORTED")?;
+            Ok(self
+                .property32(self.root, supported, AtomEnum::ATOM)?
+                .contains(&atom))
+        }
+
+        fn active_window(&self) -> Result<Window, Box<dyn std::error::Error>> {
+            let net_active_window = self.atom("_NET_ACTIVE_WINDOW")?;
+            if let Some(&window) = self
+                .property32(self.root, net_active_window, AtomEnum::WINDOW)?
+                .first()
+            {
+                if window != x11rb::NONE {
+                    return Ok(window);
+                }
+            }
+
+            // No EWMH compliant window manager (e.g. a bare Xvfb session), ask
+            // the server directly.
+            let focus = self.conn.get_input_focus()?.reply()?.focus;
+            if focus == x11rb::NONE || focus == u32::from(InputFocus::POINTER_ROOT) {
+                return Err("No window currently has keyboard focus".into());
+            }
+            Ok(focus)
+        }
+
+        fn describe(&self, window: Window) -> Result<FocusedWindow, Box<dyn std::error::Error>> {
+            let net_wm_pid = self.atom("_NET_WM_PID")?;
+            let net_wm_name = self.atom("_NET_WM_NAME")?;
+
+            let pid = self
+                .property32(window, net_wm_pid, AtomEnum::CARDINAL)?
+                .first()
+                .copied();
+            let title = match self.property_string(window, net_wm_name)? {
+                Some(title) => Some(title),
+                None => self.property_string(window, AtomEnum::WM_NAME.into())?,
+            };
+
+            Ok(FocusedWindow {
+                window_id: u64::from(window),
+                pid,
+                process_name: pid.and_then(process_name),
+                title,
+            })
+        }
+
+        fn activate(&self, window: Window) -> Result<(), Box<dyn std::error::Error>> {
+            // Fails with BadWindow if the window has been destroyed meanwhile.
+            self.conn.get_window_attributes(window)?.reply()?;
+
+            let net_active_window = self.atom("_NET_ACTIVE_WINDOW")?;
+            if self.supports(net_active_window)? {
+                let event = ClientMessageEvent::new(
+                    32,
+                    window,
+                    net_active_window,
+                    [SOURCE_PAGER, CURRENT_TIME, 0, 0, 0],
+                );
+                self.conn.send_event(
+                    false,
+                    self.root,
+                    EventMask::SUBSTRUCTURE_REDIRECT | EventMask::SUBSTRUCTURE_NOTIFY,
+                    event,
+                )?;
+            } else {
+                self.conn.configure_window(
+                    window,
+                    &ConfigureWindowAux::new().stack_mode(StackMode::ABOVE),
+                )?;
+                self.conn
+                    .set_input_focus(InputFocus::PARENT, window, CURRENT_TIME)?;
+            }
+
+            self.conn.sync()?;
+            Ok(())
+        }
+    }
+
+    pub fn get_focused_window() -> Result<FocusedWindow, Box<dyn std::error::Error>> {
+        let x11 = X11::connect()?;
+        let window = x11.active_window()?;
+        x11.describe(window)
+    }
+
+    pub fn restore_focus(window: &FocusedWindow) -> Result<(), Box<dyn std::error::Error>> {
+        let x11 = X11::connect()?;
+        let id = Window::try_from(window.window_id)
+            .map_err(|_| format!("Invalid X11 window id: {}", window.window_id))?;
+        x11.activate(id)
+            .map_err(|e| format!("Failed to activate window {}: {}", window.window_id, e).into())
+    }
+}
+
+#[cfg(not(target_os = "linux"))]
+mod backend {
+    use super::FocusedWindow;
+
+    pub fn get_focused_window() -> Result<FocusedWindow, Box<dyn std::error::Error>> {
+        Err("get-focus is not supported on this platform".into())
+    }
+
+    pub fn restore_focus(_window: &FocusedWindow) -> Result<(), Box<dyn std::error::Error>> {
+        Err("restore-focus is not supported on this platform".into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn descriptor_round_trips_through_json() {
+        let window = FocusedWindow {
+            window_id: 0x1e00007,
+            pid: Some(4242),
+            process_name: Some("code".to_string()),
+            title: Some("main.rs — speakmcp".to_string()),
+        };
+        let json = serde_json::to_string(&window).unwrap();
+        assert_eq!(serde_json::from_str::<FocusedWindow>(&json).unwrap(), window);
+    }
+
+    // Run under Xvfb: `xvfb-run cargo test -- --ignored`
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn restores_focus_on_x11() {
+        use x11rb::connection::Connection;
+        use x11rb::protocol::xproto::{
+            ConnectionExt, CreateWindowAux, InputFocus, PropMode, WindowClass,
+        };
+        use x11rb::wrapper::ConnectionExt as _;
+        use x11rb::CURRENT_TIME;
+
+        let (conn, screen_num) = x11rb::connect(None).unwrap();
+        let screen = &conn.setup().roots[screen_num];
+        let mut windows = Vec::new();
+        for title in ["first", "second"] {
+            let window = conn.generate_id().unwrap();
+            conn.create_window(
+                screen.root_depth,
+                window,
+                screen.root,
+                0,
+                0,
+                100,
+                100,
+                0,
+                WindowClass::INPUT_OUTPUT,
+                screen.root_visual,
+                &CreateWindowAux::new(),
+            )
+            .unwrap();
+            conn.change_property8(
+                PropMode::REPLACE,
+                window,
+                x11rb::protocol::xproto::AtomEnum::WM_NAME,
+                x11rb::protocol::xproto::AtomEnum::STRING,
+                title.as_bytes(),
+            )
+            .unwrap();
+            conn.map_window(window).unwrap();
+            windows.push(window);
+        }
+        conn.set_input_focus(InputFocus::PARENT, windows[0], CURRENT_TIME)
+            .unwrap();
+        conn.sync().unwrap();
+
+        let first = get_focused_window().unwrap();
+        assert_eq!(first.window_id, u64::from(windows[0]));
+        assert_eq!(first.title.as_deref(), Some("first"));
+
+        conn.set_input_focus(InputFocus::PARENT, windows[1], CURRENT_TIME)
+            .unwrap();
+        conn.sync().unwrap();
+        assert_eq!(get_focused_window().unwrap().title.as_deref(), Some("second"));
+
+        restore_focus(&first).unwrap();
+        assert_eq!(get_focused_window().unwrap(), first);
+    }
+}
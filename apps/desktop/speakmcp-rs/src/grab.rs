@@ -0,0 +1,203 @@
+use std::collections::HashSet;
+
+use rdev::{Event, EventType, Key};
+
+use crate::hotkey::{is_modifier, Claim, HotkeyEngine, HotkeyEvent};
+
+// Decides which key events still reach the focused application while the
+// keyboard is grabbed. Keys of registered shortcuts are swallowed, every
+// other event is passed on unchanged. Buttons and wheels of a grabbed
+// keyboard are always passed on, like those of mice, which aren't grabbed.
+//
+// A modifier that might become a hold (Ctrl for push-to-talk) is held back
+// until it is clear what it is: a confirmed hold swallows it, another key
+// or an early release passes it on late, so Ctrl+C and a Ctrl tap still
+// work.
+#[derive(Default)]
+pub struct Grab {
+    // Modifier presses not passed on yet, in order
+    held: Vec<Key>,
+    // Keys whose remaining repeats and release are dropped
+    swallowed: HashSet<Key>,
+    // Events to pass on, taken by the listener backend
+    forward: Vec<EventType>,
+}
+
+impl Grab {
+    // Events we typed ourselves are never held back
+    pub fn pass(&mut self, event_type: EventType) {
+        self.forward.push(event_type);
+    }
+
+    // A started hold owns the modifiers held back for it
+    pub fn confirm(&mut self, hotkeys: &[HotkeyEvent]) {
+        if hotkeys
+            .iter()
+            .any(|event| matches!(event, HotkeyEvent::HoldStart { .. }))
+        {
+            self.swallowed.extend(self.held.drain(..));
+        }
+    }
+
+    fn flush(&mut self) {
+        self.forward
+            .extend(self.held.drain(..).map(EventType::KeyPress));
+    }
+
+    // Called with the hotkey engine after it has seen `event`
+    pub fn filter(&mut self, event: &Event, hotkeys: &HotkeyEngine) {
+        match event.event_type {
+            EventType::KeyPress(key) => {
+                // Auto-repeat of a key we already decided on
+                if self.swallowed.contains(&key) || self.held.contains(&key) {
+                    return;
+                }
+                match hotkeys.claim(key) {
+                    Some(Claim::Pending) if is_modifier(key) => self.held.push(key),
+                    Some(_) => {
+                        // The whole chord stays with us
+                        self.swallowed.insert(key);
+                        self.swallowed.extend(self.held.drain(..));
+                    }
+                    None => {
+                        self.flush();
+                        self.forward.push(event.event_type);
+                    }
+                }
+            }
+            EventType::KeyRelease(key) => {
+                if self.swallowed.remove(&key) {
+                    return;
+                }
+                // A tap or a modifier that belongs to a shortcut of the
+                // application after all
+                self.flush();
+                self.forward.push(event.event_type);
+            }
+            EventType::ButtonPress(_) | EventType::ButtonRelease(_) | EventType::Wheel { .. } => {
+                // Ctrl+click has to arrive as such
+                self.flush();
+                self.forward.push(event.event_type);
+            }
+            EventType::MouseMove { .. } => {}
+        }
+    }
+
+    pub fn take(&mut self) -> Vec<EventType> {
+        std::mem::take(&mut self.forward)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, SystemTime};
+
+    use super::*;
+    use crate::hotkey::Binding;
+    use rdev::Button;
+    use crate::listener::Listener;
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    // What the application sees of `events`, replayed through a grabbing
+    // listener with the hold delay running out in between.
+    fn forwarded(events: &[(u64, EventType)]) -> Vec<EventType> {
+        let bindings: Vec<Binding> = serde_json::from_str(
+            r#"[{"id":"record","combo":"ctrl","mode":"hold"},
+                {"id":"mcp","combo":"ctrl-slash","mode":"toggle"}]"#,
+        )
+        .unwrap();
+        let mut listener = Listener::default();
+        listener.hotkeys = HotkeyEngine::new(&bindings).unwrap();
+        listener.grab = Some(Grab::default());
+
+        let mut out = Vec::new();
+        for (ms, event_type) in events {
+            listener.tick(at(*ms));
+            listener.process(Event {
+                time: at(*ms),
+                name: None,
+                event_type: *event_type,
+            });
+            out.extend(listener.take_forwarded());
+        }
+        out
+    }
+
+    #[test]
+    fn swallows_shortcuts_and_passes_everything_else() {
+        use EventType::{KeyPress as Press, KeyRelease as Release};
+
+        // Push-to-talk hold: the application sees nothing
+        assert!(forwarded(&[
+            (0, Press(Key::ControlLeft)),
+            (900, Press(Key::ControlLeft)),
+            (1200, Release(Key::ControlLeft)),
+        ])
+        .is_empty());
+
+        // Ctrl+/ is ours, the application only sees the plain typing
+        assert_eq!(
+            forwarded(&[
+                (0, Press(Key::KeyA)),
+                (50, Release(Key::KeyA)),
+                (100, Press(Key::ControlLeft)),
+                (150, Press(Key::Slash)),
+                (200, Release(Key::Slash)),
+                (250, Release(Key::ControlLeft)),
+            ]),
+            vec![Press(Key::KeyA), Release(Key::KeyA)]
+        );
+
+        // Ctrl+C and a Ctrl tap arrive late but complete
+        assert_eq!(
+            forwarded(&[
+                (0, Press(Key::ControlLeft)),
+                (100, Press(Key::KeyC)),
+                (150, Release(Key::KeyC)),
+                (200, Release(Key::ControlLeft)),
+                (300, Press(Key::ControlRight)),
+                (350, Release(Key::ControlRight)),
+            ]),
+            vec![
+                Press(Key::ControlLeft),
+                Press(Key::KeyC),
+                Release(Key::KeyC),
+                Release(Key::ControlLeft),
+                Press(Key::ControlRight),
+                Release(Key::ControlRight),
+            ]
+        );
+
+        // A receiver's mouse on the grabbed keyboard keeps working, also
+        // while a modifier is held back
+        let click = [
+            EventType::ButtonPress(Button::Left),
+            EventType::ButtonRelease(Button::Left),
+            EventType::Wheel {
+                delta_x: 0,
+                delta_y: -1,
+            },
+        ];
+        assert_eq!(
+            forwarded(&[(0, click[0]), (50, click[1]), (100, click[2])]),
+            click
+        );
+        assert_eq!(
+            forwarded(&[
+                (0, Press(Key::ControlLeft)),
+                (100, click[0]),
+                (150, click[1]),
+                (200, Release(Key::ControlLeft)),
+            ]),
+            vec![
+                Press(Key::ControlLeft),
+                click[0],
+                click[1],
+                Release(Key::ControlLeft),
+            ]
+        );
+    }
+}
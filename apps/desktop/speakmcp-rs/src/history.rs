@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+use std::fmt;
+
+use enigo::{Direction, Key};
+use serde::{Deserialize, Serialize};
+
+use crate::backend::Backend;
+use crate::focus::FocusedWindow;
+use crate::keyseq;
+
+pub const HISTORY_SIZE: usize = 20;
+
+#[derive(Deserialize, Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum UndoMethod {
+    // One backspace per inserted character
+    #[default]
+    Backspace,
+    // The application's undo shortcut, once
+    Chord,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Insertion {
+    // Characters that actually reached the window
+    pub chars: usize,
+    // Focus at the time of the insertion, None if it couldn't be determined
+    pub window: Option<FocusedWindow>,
+    // Whether key actions ran as part of it, which backspaces can't revert
+    pub keys: bool,
+}
+
+#[derive(Debug, PartialEq)]
+pub enum UndoError {
+    Empty,
+    UnknownWindow,
+    FocusChanged,
+    NotText,
+}
+
+impl fmt::Display for UndoError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            UndoError::Empty => write!(f, "Nothing to undo"),
+            UndoError::UnknownWindow => {
+                write!(f, "The focused window is unknown, refusing to undo")
+            }
+            UndoError::FocusChanged => {
+                write!(f, "Focus moved to another window since the insertion")
+            }
+            UndoError::NotText => {
+                write!(f, "The insertion sent key actions, use the chord method to undo it")
+            }
+        }
+    }
+}
+
+impl std::error::Error for UndoError {}
+
+// The last insertions made by the daemon, newest at the back.
+pub struct History {
+    entries: VecDeque<Insertion>,
+    capacity: usize,
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new(HISTORY_SIZE)
+    }
+}
+
+impl History {
+    pub fn new(capacity: usize) -> Self {
+        History {
+            entries: VecDeque::new(),
+            capacity,
+        }
+    }
+
+    pub fn record(&mut self, insertion: Insertion) {
+        if insertion.chars == 0 && !insertion.keys {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+        self.entries.push_back(insertion);
+    }
+
+    // Takes the newest insertion if `method` can revert it in the currently
+    // focused window. Refused insertions stay in the history.
+    pub fn take_last(
+        &mut self,
+        focused: Option<&FocusedWindow>,
+        method: UndoMethod,
+    ) -> Result<Insertion, UndoError> {
+        let last = self.entries.back().ok_or(UndoError::Empty)?;
+        let (Some(window), Some(focused)) = (&last.window, focused) else {
+            return Err(UndoError::UnknownWindow);
+        };
+        if window.window_id != focused.window_id {
+            return Err(UndoError::FocusChanged);
+        }
+        if last.keys && method == UndoMethod::Backspace {
+            return Err(UndoError::NotText);
+        }
+        Ok(self.entries.pop_back().unwrap())
+    }
+}
+
+pub fn undo(
+    backend: &mut dyn Backend,
+    insertion: &Insertion,
+    method: UndoMethod,
+) -> Result<(), Box<dyn std::error::Error>> {
+    match method {
+        UndoMethod::Backspace => {
+            for _ in 0..insertion.chars {
+                backend
+                    .key(Key::Backspace, Direction::Click)
+                    .map_err(|e| format!("Failed to send backspace with the {} backend: {}", backend.name(), e))?;
+            }
+            Ok(())
+        }
+        UndoMethod::Chord => {
+            let actions = keyseq::parse(&format!("{{{}+z}}", keyseq::primary_modifier()))?;
+            keyseq::execute(backend, &actions)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(window_id: u64) -> FocusedWindow {
+        FocusedWindow {
+            window_id,
+            pid: None,
+            process_name: None,
+            title: None,
+        }
+    }
+
+    fn insertion(chars: usize, window_id: u64) -> Insertion {
+        Insertion {
+            chars,
+            window: Some(window(window_id)),
+            keys: false,
+        }
+    }
+
+    #[test]
+    fn undoes_newest_first_only_in_same_window() {
+        let mut history = History::new(2);
+        history.record(insertion(3, 1));
+        history.record(insertion(5, 1));
+        history.record(insertion(7, 2));
+
+        assert_eq!(
+            history.take_last(Some(&window(1)), UndoMethod::Backspace),
+            Err(UndoError::FocusChanged)
+        );
+        assert_eq!(history.take_last(None, UndoMethod::Backspace), Err(UndoError::UnknownWindow));
+        let last = history.take_last(Some(&window(2)), UndoMethod::Backspace).unwrap();
+        assert_eq!(last.chars, 7);
+        let last = history.take_last(Some(&window(1)), UndoMethod::Backspace).unwrap();
+        assert_eq!(last.chars, 5);
+        // The oldest one fell out of the history
+        assert_eq!(
+            history.take_last(Some(&window(1)), UndoMethod::Backspace),
+            Err(UndoError::Empty)
+        );
+    }
+
+    #[test]
+    fn key_actions_need_the_undo_chord() {
+        let mut history = History::default();
+        history.record(Insertion {
+            keys: true,
+            ..insertion(4, 1)
+        });
+        assert_eq!(
+            history.take_last(Some(&window(1)), UndoMethod::Backspace),
+            Err(UndoError::NotText)
+        );
+        assert!(history.take_last(Some(&window(1)), UndoMethod::Chord).is_ok());
+    }
+}
@@ -0,0 +1,948 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, SystemTime};
+
+use rdev::{Button, Event, EventType, Key};
+use serde::{Deserialize, Serialize};
+
+pub const DEFAULT_HOLD_DELAY_MS: u64 = 800;
+pub const DEFAULT_SEQUENCE_TIMEOUT_MS: u64 = 500;
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Modifiers {
+    pub ctrl: bool,
+    pub shift: bool,
+    pub alt: bool,
+    pub meta: bool,
+}
+
+impl Modifiers {
+    pub fn from_keys<'a>(keys: impl IntoIterator<Item = &'a Key>) -> Self {
+        let mut modifiers = Modifiers::default();
+        for key in keys {
+            if let Some(flag) = modifiers.flag(*key) {
+                *flag = true;
+            }
+        }
+        modifiers
+    }
+
+    fn flag(&mut self, key: Key) -> Option<&mut bool> {
+        match key {
+            Key::ControlLeft | Key::ControlRight => Some(&mut self.ctrl),
+            Key::ShiftLeft | Key::ShiftRight => Some(&mut self.shift),
+            Key::Alt | Key::AltGr => Some(&mut self.alt),
+            Key::MetaLeft | Key::MetaRight => Some(&mut self.meta),
+            _ => None,
+        }
+    }
+
+    fn contains(mut self, key: Key) -> bool {
+        self.flag(key).is_some_and(|flag| *flag)
+    }
+}
+
+pub fn is_modifier(key: Key) -> bool {
+    Modifiers::default().flag(key).is_some()
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Wheel {
+    Up,
+    Down,
+    Left,
+    Right,
+}
+
+// Anything a combo can be made of. Wheel steps press and release at once,
+// so they can only toggle.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Input {
+    Key(Key),
+    Button(Button),
+    Wheel(Wheel),
+}
+
+impl Input {
+    // The name combos use for it
+    fn name(self) -> String {
+        match self {
+            Input::Key(key) => normalize_key(&format!("{:?}", key)),
+            Input::Button(button) => button_name(button),
+            Input::Wheel(wheel) => format!("wheel{:?}", wheel).to_lowercase(),
+        }
+    }
+
+    fn is_modifier(self) -> bool {
+        matches!(self, Input::Key(key) if is_modifier(key))
+    }
+}
+
+pub fn button_name(button: Button) -> String {
+    // The side buttons are 8 and 9 under X11 and XBUTTON1 and 2 on Windows
+    let back = if cfg!(target_os = "windows") { 1 } else { 8 };
+    match button {
+        Button::Left => "mouseleft".to_string(),
+        Button::Right => "mouseright".to_string(),
+        Button::Middle => "mousemiddle".to_string(),
+        Button::Unknown(code) if code == back => "mouseback".to_string(),
+        Button::Unknown(code) if code == back + 1 => "mouseforward".to_string(),
+        Button::Unknown(code) => format!("mousebutton{}", code),
+    }
+}
+
+// Numbered names for the buttons, as games and browsers count them
+fn mouse_alias(key: &str) -> Option<&'static str> {
+    match key {
+        "mouse1" => Some("mouseleft"),
+        "mouse2" => Some("mouseright"),
+        "mouse3" => Some("mousemiddle"),
+        "mouse4" => Some("mouseback"),
+        "mouse5" => Some("mouseforward"),
+        _ => None,
+    }
+}
+
+fn wheel(delta_x: i64, delta_y: i64) -> Option<Wheel> {
+    match (delta_x.signum(), delta_y.signum()) {
+        (_, 1) => Some(Wheel::Up),
+        (_, -1) => Some(Wheel::Down),
+        (-1, _) => Some(Wheel::Left),
+        (1, _) => Some(Wheel::Right),
+        _ => None,
+    }
+}
+
+// Mirrors `matchesKeyCombo` in shared/key-utils.ts so combos registered from
+// the settings UI resolve to the same keys on both sides.
+pub fn normalize_key(name: &str) -> String {
+    let mut key = name.to_lowercase();
+    // KeyA -> a, Num1 -> 1
+    if (key.len() > 3 && key.starts_with("key")) || (key.len() == 4 && key.starts_with("num")) {
+        key = key[3..].to_string();
+    }
+
+    let mapped = match key.as_str() {
+        "slash" => "/",
+        "comma" => ",",
+        "space" => " ",
+        "return" => "enter",
+        "arrowup" | "uparrow" => "up",
+        "arrowdown" | "downarrow" => "down",
+        "arrowleft" | "leftarrow" => "left",
+        "arrowright" | "rightarrow" => "right",
+        "function" => "fn",
+        _ => return key,
+    };
+    mapped.to_string()
+}
+
+// The character each key types on the active layout, see `MatchBy::Char`
+type Chars = HashMap<Key, char>;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct KeyCombo {
+    pub modifiers: Modifiers,
+    // None for modifier-only combos such as "ctrl" or "ctrl-alt"
+    pub key: Option<String>,
+    pub match_by: MatchBy,
+}
+
+impl KeyCombo {
+    // Same grammar as `parseKeyCombo`: dash separated, case insensitive,
+    // `ctrl`, `shift`, `alt`, `meta`/`cmd` modifiers and at most one key.
+    pub fn parse(combo: &str) -> Result<Self, String> {
+        if combo.trim().is_empty() {
+            return Err("Key combination cannot be empty".to_string());
+        }
+
+        let mut modifiers = Modifiers::default();
+        let mut key = None;
+        for part in combo.to_lowercase().split('-') {
+            match part {
+                "ctrl" => modifiers.ctrl = true,
+                "shift" => modifiers.shift = true,
+                "alt" => modifiers.alt = true,
+                "meta" | "cmd" => modifiers.meta = true,
+                "" => return Err(format!("Empty key in combination: {}", combo)),
+                _ => {
+                    if key.is_some() {
+                        return Err(format!("More than one key in combination: {}", combo));
+                    }
+                    let name = normalize_key(part);
+                    key = Some(mouse_alias(&name).map_or(name, str::to_string));
+                }
+            }
+        }
+
+        Ok(KeyCombo {
+            modifiers,
+            key,
+            match_by: MatchBy::Position,
+        })
+    }
+
+    fn is_main(&self, input: Input, chars: &Chars) -> bool {
+        let Some(main) = self.key.as_deref() else {
+            return false;
+        };
+        // Keys that type nothing keep their names
+        if let (MatchBy::Char, Input::Key(key)) = (self.match_by, input) {
+            if let Some(c) = chars.get(&key) {
+                return normalize_key(&c.to_string()) == main;
+            }
+        }
+        main == input.name()
+    }
+
+    fn involves(&self, input: Input, chars: &Chars) -> bool {
+        let is_modifier = matches!(input, Input::Key(key) if self.modifiers.contains(key));
+        is_modifier || self.is_main(input, chars)
+    }
+
+    fn is_wheel(&self) -> bool {
+        self.key.as_deref().is_some_and(|key| key.starts_with("wheel"))
+    }
+
+    // Exact match: the same modifiers, the main key (if any) and nothing else
+    fn matches(&self, pressed: &HashSet<Input>, chars: &Chars) -> bool {
+        if modifiers(pressed.iter()) != self.modifiers {
+            return false;
+        }
+        let mut others = pressed.iter().filter(|input| !input.is_modifier());
+        match self.key {
+            Some(_) => match (others.next(), others.next()) {
+                (Some(input), None) => self.is_main(*input, chars),
+                _ => false,
+            },
+            None => others.next().is_none(),
+        }
+    }
+
+    // Whether pressing `input` completes the combo as a step of a sequence.
+    // Unlike `matches` the main key may be a modifier itself, so "altgr"
+    // means Right Alt alone.
+    fn completed_by(&self, input: Input, pressed: &HashSet<Input>, chars: &Chars) -> bool {
+        if self.key.is_none() {
+            return self.involves(input, chars) && self.matches(pressed, chars);
+        }
+        let held = pressed.iter().filter(|other| **other != input);
+        self.is_main(input, chars) && modifiers(held) == self.modifiers
+    }
+}
+
+fn modifiers<'a>(inputs: impl Iterator<Item = &'a Input>) -> Modifiers {
+    Modifiers::from_keys(inputs.filter_map(|input| match input {
+        Input::Key(key) => Some(key),
+        _ => None,
+    }))
+}
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Mode {
+    #[default]
+    Hold,
+    Toggle,
+    // Combos pressed one after the other, "altgr, altgr" or "ctrl, d"
+    Sequence,
+}
+
+// What the key of a combo names. Modifiers are always matched by position.
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum MatchBy {
+    // The key at that place on a US keyboard, "ctrl-z" stays next to the
+    // left Shift on AZERTY
+    #[default]
+    Position,
+    // The key that types the character on the active layout. Keys that
+    // type nothing, such as F1, are still matched by name.
+    Char,
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct Binding {
+    pub id: String,
+    pub combo: String,
+    #[serde(default)]
+    pub mode: Mode,
+    #[serde(default = "default_hold_delay_ms")]
+    pub hold_delay_ms: u64,
+    // Longest pause between the steps of a sequence
+    #[serde(default = "default_sequence_timeout_ms")]
+    pub sequence_timeout_ms: u64,
+    #[serde(default)]
+    pub match_by: MatchBy,
+}
+
+fn default_hold_delay_ms() -> u64 {
+    DEFAULT_HOLD_DELAY_MS
+}
+
+fn default_sequence_timeout_ms() -> u64 {
+    DEFAULT_SEQUENCE_TIMEOUT_MS
+}
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+#[serde(tag = "event_type")]
+pub enum HotkeyEvent {
+    // `pressed` is when the shortcut went down, hold_delay_ms before `time`
+    #[serde(rename = "HotkeyHoldStart")]
+    HoldStart {
+        id: String,
+        time: SystemTime,
+        pressed: SystemTime,
+    },
+    // `cancelled` is set when another key interrupted the hold, as opposed to
+    // the user releasing the shortcut.
+    #[serde(rename = "HotkeyHoldEnd")]
+    HoldEnd {
+        id: String,
+        time: SystemTime,
+        cancelled: bool,
+    },
+    #[serde(rename = "HotkeyToggled")]
+    Toggled {
+        id: String,
+        time: SystemTime,
+    },
+    // The last step of a sequence was pressed in time
+    #[serde(rename = "HotkeyTriggered")]
+    Triggered {
+        id: String,
+        time: SystemTime,
+    },
+}
+
+// How a registered shortcut is using a key, see `HotkeyEngine::claim`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Claim {
+    // Part of a hold that hasn't reached its delay yet
+    Pending,
+    // Part of a combo that is pressed right now or a hold that started
+    Active,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum HoldState {
+    Idle,
+    Pending { deadline: SystemTime },
+    Holding,
+}
+
+struct Registered {
+    id: String,
+    // The first step of a sequence
+    combo: KeyCombo,
+    mode: Mode,
+    hold_delay: Duration,
+    state: HoldState,
+    // Every step of a sequence, empty for other modes
+    steps: Vec<KeyCombo>,
+    sequence_timeout: Duration,
+    // Next step of the sequence and when the previous one was pressed
+    progress: Option<(usize, SystemTime)>,
+}
+
+impl Registered {
+    // Moves a sequence on by a press. A step that isn't next, or comes too
+    // late, starts the sequence over; modifiers pressed on the way to the
+    // next step don't.
+    fn advance(
+        &mut self,
+        input: Input,
+        pressed: &HashSet<Input>,
+        chars: &Chars,
+        time: SystemTime,
+        out: &mut Vec<HotkeyEvent>,
+    ) {
+        let next = match self.progress {
+            Some((step, last)) if time <= last + self.sequence_timeout => step,
+            _ => 0,
+        };
+        if self.steps[next].completed_by(input, pressed, chars) {
+            if next + 1 < self.steps.len() {
+                self.progress = Some((next + 1, time));
+            } else {
+                self.progress = None;
+                out.push(HotkeyEvent::Triggered {
+                    id: self.id.clone(),
+                    time,
+                });
+            }
+        } else if self.steps[0].completed_by(input, pressed, chars) {
+            self.progress = Some((1, time));
+        } else if next == 0 || !input.is_modifier() {
+            self.progress = None;
+        }
+    }
+}
+
+#[derive(Default)]
+pub struct HotkeyEngine {
+    bindings: Vec<Registered>,
+    pressed: HashSet<Input>,
+    chars: Chars,
+}
+
+impl HotkeyEngine {
+    pub fn new(bindings: &[Binding]) -> Result<Self, String> {
+        let mut engine = HotkeyEngine::default();
+        engine.set_bindings(bindings)?;
+        Ok(engine)
+    }
+
+    pub fn set_bindings(&mut self, bindings: &[Binding]) -> Result<(), String> {
+        self.bindings = bindings
+            .iter()
+            .map(|binding| {
+                let invalid = |e: String| format!("Invalid hotkey {}: {}", binding.id, e);
+                let parse = |combo: &str| {
+                    KeyCombo::parse(combo).map(|combo| KeyCombo {
+                        match_by: binding.match_by,
+                        ..combo
+                    })
+                };
+                let steps = if binding.mode == Mode::Sequence {
+                    let steps = binding
+                        .combo
+                        .split(',')
+                        .map(|step| parse(step.trim()))
+                        .collect::<Result<Vec<_>, _>>()
+                        .map_err(invalid)?;
+                    if steps.len() < 2 {
+                        return Err(invalid(
+                            "a sequence needs at least two comma separated steps".to_string(),
+                        ));
+                    }
+                    steps
+                } else if binding.combo.contains(',') {
+                    return Err(invalid(
+                        "key sequences need the \"sequence\" mode".to_string(),
+                    ));
+                } else {
+                    Vec::new()
+                };
+                let combo = match steps.first() {
+                    Some(first) => first.clone(),
+                    None => parse(&binding.combo).map_err(invalid)?,
+                };
+                if combo.is_wheel() && binding.mode == Mode::Hold {
+                    return Err(invalid("the mouse wheel can only toggle".to_string()));
+                }
+                Ok(Registered {
+                    id: binding.id.clone(),
+                    combo,
+                    mode: binding.mode,
+                    hold_delay: Duration::from_millis(binding.hold_delay_ms),
+                    state: HoldState::Idle,
+                    steps,
+                    sequence_timeout: Duration::from_millis(binding.sequence_timeout_ms),
+                    progress: None,
+                })
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(())
+    }
+
+    // True for modifiers and keys that belong to a registered shortcut
+    pub fn is_relevant(&self, key: Key) -> bool {
+        is_modifier(key)
+            || self
+                .bindings
+                .iter()
+                .flat_map(|binding| std::iter::once(&binding.combo).chain(&binding.steps))
+                .any(|combo| combo.is_main(Input::Key(key), &self.chars))
+    }
+
+    // Tells the engine what `key` types on the active layout, before it
+    // handles the press
+    pub fn set_char(&mut self, key: Key, c: Option<char>) {
+        match c {
+            Some(c) => self.chars.insert(key, c),
+            None => self.chars.remove(&key),
+        };
+    }
+
+    // Whether a shortcut owns `key` in the current state, strongest claim
+    // first. Grabbing keeps claimed keys from the focused application.
+    pub fn claim(&self, key: Key) -> Option<Claim> {
+        let mut claim = None;
+        for binding in &self.bindings {
+            if !binding.combo.involves(Input::Key(key), &self.chars) {
+                continue;
+            }
+            match (binding.mode, binding.state) {
+                (Mode::Toggle, _) if binding.combo.matches(&self.pressed, &self.chars) => {
+                    return Some(Claim::Active)
+                }
+                (Mode::Hold, HoldState::Holding) => return Some(Claim::Active),
+                (Mode::Hold, HoldState::Pending { .. }) => claim = Some(Claim::Pending),
+                _ => {}
+            }
+        }
+        claim
+    }
+
+    pub fn handle(&mut self, event: &Event) -> Vec<HotkeyEvent> {
+        // Fire any hold that matured before this event so synthetic streams
+        // with timestamps behave like the real timer.
+        let mut out = self.tick(event.time);
+
+        match event.event_type {
+            EventType::KeyPress(key) => self.press(Input::Key(key), event.time, &mut out),
+            EventType::KeyRelease(key) => self.release(Input::Key(key), event.time, &mut out),
+            EventType::ButtonPress(button) => {
+                self.press(Input::Button(button), event.time, &mut out)
+            }
+            EventType::ButtonRelease(button) => {
+                self.release(Input::Button(button), event.time, &mut out)
+            }
+            EventType::Wheel { delta_x, delta_y } => {
+                if let Some(wheel) = wheel(delta_x, delta_y) {
+                    self.press(Input::Wheel(wheel), event.time, &mut out);
+                    self.release(Input::Wheel(wheel), event.time, &mut out);
+                }
+            }
+            EventType::MouseMove { .. } => {}
+        }
+
+        out
+    }
+
+    fn press(&mut self, input: Input, time: SystemTime, out: &mut Vec<HotkeyEvent>) {
+        // Auto-repeat sends further presses without a release
+        if !self.pressed.insert(input) {
+            return;
+        }
+        for binding in &mut self.bindings {
+            if binding.mode == Mode::Sequence {
+                binding.advance(input, &self.pressed, &self.chars, time, out);
+            } else if binding.combo.involves(input, &self.chars)
+                && binding.combo.matches(&self.pressed, &self.chars)
+            {
+                if binding.mode == Mode::Toggle {
+                    out.push(HotkeyEvent::Toggled {
+                        id: binding.id.clone(),
+                        time,
+                    });
+                } else if binding.state == HoldState::Idle {
+                    binding.state = HoldState::Pending {
+                        deadline: time + binding.hold_delay,
+                    };
+                }
+            } else if binding.mode == Mode::Hold {
+                // Any other key interrupts a pending or active hold
+                if binding.state == HoldState::Holding {
+                    out.push(HotkeyEvent::HoldEnd {
+                        id: binding.id.clone(),
+                        time,
+                        cancelled: true,
+                    });
+                }
+                binding.state = HoldState::Idle;
+            }
+        }
+    }
+
+    fn release(&mut self, input: Input, time: SystemTime, out: &mut Vec<HotkeyEvent>) {
+        self.pressed.remove(&input);
+        for binding in &mut self.bindings {
+            if binding.mode != Mode::Hold || !binding.combo.involves(input, &self.chars) {
+                continue;
+            }
+            if binding.state == HoldState::Holding {
+                out.push(HotkeyEvent::HoldEnd {
+                    id: binding.id.clone(),
+                    time,
+                    cancelled: false,
+                });
+            }
+            binding.state = HoldState::Idle;
+        }
+    }
+
+    pub fn tick(&mut self, now: SystemTime) -> Vec<HotkeyEvent> {
+        let mut out = Vec::new();
+        for binding in &mut self.bindings {
+            if let HoldState::Pending { deadline } = binding.state {
+                if now < deadline {
+                    continue;
+                }
+                if binding.combo.matches(&self.pressed, &self.chars) {
+                    binding.state = HoldState::Holding;
+                    out.push(HotkeyEvent::HoldStart {
+                        id: binding.id.clone(),
+                        time: deadline,
+                        pressed: deadline - binding.hold_delay,
+                    });
+                } else {
+                    binding.state = HoldState::Idle;
+                }
+            }
+        }
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    fn press(ms: u64, key: Key) -> Event {
+        Event {
+            time: at(ms),
+            name: None,
+            event_type: EventType::KeyPress(key),
+        }
+    }
+
+    fn release(ms: u64, key: Key) -> Event {
+        Event {
+            time: at(ms),
+            name: None,
+            event_type: EventType::KeyRelease(key),
+        }
+    }
+
+    fn binding(id: &str, combo: &str, mode: Mode) -> Binding {
+        Binding {
+            id: id.to_string(),
+            combo: combo.to_string(),
+            mode,
+            hold_delay_ms: DEFAULT_HOLD_DELAY_MS,
+            sequence_timeout_ms: DEFAULT_SEQUENCE_TIMEOUT_MS,
+            match_by: MatchBy::Position,
+        }
+    }
+
+    fn run(engine: &mut HotkeyEngine, events: &[Event]) -> Vec<HotkeyEvent> {
+        events.iter().flat_map(|event| engine.handle(event)).collect()
+    }
+
+    #[test]
+    fn parses_combos_like_the_settings_ui() {
+        let combo = KeyCombo::parse("Ctrl-Shift-Slash").unwrap();
+        assert!(combo.modifiers.ctrl && combo.modifiers.shift);
+        assert_eq!(combo.key.as_deref(), Some("/"));
+
+        let combo = KeyCombo::parse("cmd-alt").unwrap();
+        assert!(combo.modifiers.meta && combo.modifiers.alt);
+        assert_eq!(combo.key, None);
+
+        assert!(KeyCombo::parse("").is_err());
+        assert!(KeyCombo::parse("ctrl-a-b").is_err());
+    }
+
+    #[test]
+    fn hold_ctrl_starts_after_delay_and_ends_on_release() {
+        let mut engine = HotkeyEngine::new(&[binding("record", "ctrl", Mode::Hold)]).unwrap();
+        let events = run(
+            &mut engine,
+            &[press(0, Key::ControlLeft), release(1000, Key::ControlLeft)],
+        );
+        assert_eq!(
+            events,
+            vec![
+                HotkeyEvent::HoldStart {
+                    id: "record".to_string(),
+                    time: at(800),
+                    pressed: at(0),
+                },
+                HotkeyEvent::HoldEnd {
+                    id: "record".to_string(),
+                    time: at(1000),
+                    cancelled: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn short_tap_and_ctrl_shortcuts_do_not_start_a_hold() {
+        let mut engine = HotkeyEngine::new(&[binding("record", "ctrl", Mode::Hold)]).unwrap();
+        let events = run(
+            &mut engine,
+            &[
+                press(0, Key::ControlLeft),
+                release(300, Key::ControlLeft),
+                press(1000, Key::ControlLeft),
+                press(1100, Key::KeyC),
+                release(1200, Key::KeyC),
+                release(2500, Key::ControlLeft),
+            ],
+        );
+        assert!(events.is_empty());
+    }
+
+    #[test]
+    fn ctrl_alt_hold_takes_over_from_ctrl_hold() {
+        let mut engine = HotkeyEngine::new(&[
+            binding("record", "ctrl", Mode::Hold),
+            binding("mcp", "ctrl-alt", Mode::Hold),
+        ])
+        .unwrap();
+        let events = run(
+            &mut engine,
+            &[
+                press(0, Key::ControlLeft),
+                press(200, Key::Alt),
+                release(1500, Key::Alt),
+                release(1600, Key::ControlLeft),
+            ],
+        );
+        assert_eq!(
+            events,
+            vec![
+                HotkeyEvent::HoldStart {
+                    id: "mcp".to_string(),
+                    time: at(1000),
+                    pressed: at(200),
+                },
+                HotkeyEvent::HoldEnd {
+                    id: "mcp".to_string(),
+                    time: at(1500),
+                    cancelled: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn other_key_cancels_an_active_hold() {
+        let mut engine = HotkeyEngine::new(&[binding("record", "ctrl", Mode::Hold)]).unwrap();
+        let events = run(
+            &mut engine,
+            &[press(0, Key::ControlRight), press(900, Key::KeyV)],
+        );
+        assert_eq!(
+            events.last(),
+            Some(&HotkeyEvent::HoldEnd {
+                id: "record".to_string(),
+                time: at(900),
+                cancelled: true,
+            })
+        );
+    }
+
+    #[test]
+    fn toggle_fires_once_per_press_ignoring_auto_repeat() {
+        let mut engine =
+            HotkeyEngine::new(&[binding("record", "ctrl-slash", Mode::Toggle)]).unwrap();
+        let events = run(
+            &mut engine,
+            &[
+                press(0, Key::ControlLeft),
+                press(10, Key::Slash),
+                press(500, Key::Slash),
+                release(600, Key::Slash),
+                press(700, Key::Slash),
+                release(800, Key::Slash),
+                release(900, Key::ControlLeft),
+            ],
+        );
+        assert_eq!(
+            events,
+            vec![
+                HotkeyEvent::Toggled {
+                    id: "record".to_string(),
+                    time: at(10),
+                },
+                HotkeyEvent::Toggled {
+                    id: "record".to_string(),
+                    time: at(700),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn tick_confirms_hold_without_further_events() {
+        let mut engine = HotkeyEngine::new(&[binding("record", "ctrl", Mode::Hold)]).unwrap();
+        assert!(engine.handle(&press(0, Key::ControlLeft)).is_empty());
+        assert!(engine.tick(at(799)).is_empty());
+        assert_eq!(engine.tick(at(801)).len(), 1);
+        assert!(engine.tick(at(900)).is_empty());
+    }
+
+    #[test]
+    fn mouse_buttons_and_wheel_trigger_hotkeys() {
+        let mut engine = HotkeyEngine::new(&[
+            binding("record", "mousemiddle", Mode::Hold),
+            binding("mcp", "ctrl-mouse4", Mode::Toggle),
+            binding("zoom", "ctrl-wheelup", Mode::Toggle),
+        ])
+        .unwrap();
+        let event = |ms, event_type| Event {
+            time: at(ms),
+            name: None,
+            event_type,
+        };
+        let back = Button::Unknown(if cfg!(target_os = "windows") { 1 } else { 8 });
+        let wheel_up = EventType::Wheel {
+            delta_x: 0,
+            delta_y: 1,
+        };
+        let events = run(
+            &mut engine,
+            &[
+                event(0, EventType::ButtonPress(Button::Middle)),
+                event(1000, EventType::ButtonRelease(Button::Middle)),
+                press(2000, Key::ControlLeft),
+                event(2100, EventType::ButtonPress(back)),
+                event(2200, EventType::ButtonRelease(back)),
+                event(2300, wheel_up),
+                release(2400, Key::ControlLeft),
+                event(2500, wheel_up),
+            ],
+        );
+        let ids: Vec<&str> = events
+            .iter()
+            .map(|event| match event {
+                HotkeyEvent::HoldStart { id, .. }
+                | HotkeyEvent::HoldEnd { id, .. }
+                | HotkeyEvent::Toggled { id, .. }
+                | HotkeyEvent::Triggered { id, .. } => id.as_str(),
+            })
+            .collect();
+        assert_eq!(ids, vec!["record", "record", "mcp", "zoom"]);
+
+        let error = HotkeyEngine::new(&[binding("zoom", "wheeldown", Mode::Hold)]);
+        assert!(error.is_err());
+    }
+
+    #[test]
+    fn sequences_trigger_once_within_the_timeout() {
+        let mut double_tap = binding("dictate", "altgr, altgr", Mode::Sequence);
+        double_tap.sequence_timeout_ms = 300;
+        let mut engine = HotkeyEngine::new(&[
+            double_tap,
+            binding("mcp", "ctrl, d", Mode::Sequence),
+        ])
+        .unwrap();
+        let triggered = |events: Vec<HotkeyEvent>| -> Vec<(String, SystemTime)> {
+            events
+                .into_iter()
+                .map(|event| match event {
+                    HotkeyEvent::Triggered { id, time } => (id, time),
+                    other => panic!("unexpected {:?}", other),
+                })
+                .collect()
+        };
+
+        // Double tap in time, auto-repeat doesn't count as a second tap
+        let events = run(
+            &mut engine,
+            &[
+                press(0, Key::AltGr),
+                press(30, Key::AltGr),
+                release(80, Key::AltGr),
+                press(250, Key::AltGr),
+                release(300, Key::AltGr),
+            ],
+        );
+        assert_eq!(triggered(events), vec![("dictate".to_string(), at(250))]);
+
+        // Too slow: the second tap only starts a new sequence, which the
+        // third one completes
+        let events = run(
+            &mut engine,
+            &[
+                press(1000, Key::AltGr),
+                release(1050, Key::AltGr),
+                press(1400, Key::AltGr),
+                release(1450, Key::AltGr),
+                press(1600, Key::AltGr),
+                release(1650, Key::AltGr),
+            ],
+        );
+        assert_eq!(triggered(events), vec![("dictate".to_string(), at(1600))]);
+
+        // Another key in between breaks the double tap
+        let events = run(
+            &mut engine,
+            &[
+                press(2000, Key::AltGr),
+                release(2050, Key::AltGr),
+                press(2100, Key::KeyA),
+                release(2150, Key::KeyA),
+                press(2200, Key::AltGr),
+                release(2250, Key::AltGr),
+            ],
+        );
+        assert!(events.is_empty());
+
+        // Leader key: Ctrl, then D on its own. Ctrl+D is a different shortcut.
+        let events = run(
+            &mut engine,
+            &[
+                press(3000, Key::ControlLeft),
+                press(3100, Key::KeyD),
+                release(3150, Key::KeyD),
+                release(3200, Key::ControlLeft),
+                press(4000, Key::ControlLeft),
+                release(4050, Key::ControlLeft),
+                press(4300, Key::KeyD),
+                release(4350, Key::KeyD),
+            ],
+        );
+        assert_eq!(triggered(events), vec![("mcp".to_string(), at(4300))]);
+
+        assert!(HotkeyEngine::new(&[binding("x", "ctrl", Mode::Sequence)]).is_err());
+        assert!(HotkeyEngine::new(&[binding("x", "ctrl, d", Mode::Toggle)]).is_err());
+    }
+
+    #[test]
+    fn char_combos_follow_the_layout() {
+        let by_char = |id, combo| Binding {
+            match_by: MatchBy::Char,
+            ..binding(id, combo, Mode::Toggle)
+        };
+        // F1 types nothing and keeps its name
+        let mut engine = HotkeyEngine::new(&[
+            by_char("undo", "ctrl-a"),
+            binding("select", "ctrl-a", Mode::Toggle),
+            by_char("help", "ctrl-f1"),
+        ])
+        .unwrap();
+        // AZERTY: A sits where Q is on a US keyboard and the other way round
+        engine.set_char(Key::KeyQ, Some('a'));
+        engine.set_char(Key::KeyA, Some('q'));
+
+        let events = run(
+            &mut engine,
+            &[
+                press(0, Key::ControlLeft),
+                press(100, Key::KeyQ),
+                release(150, Key::KeyQ),
+                press(200, Key::KeyA),
+                release(250, Key::KeyA),
+                press(300, Key::F1),
+                release(350, Key::F1),
+                release(400, Key::ControlLeft),
+            ],
+        );
+        assert_eq!(
+            events,
+            vec![
+                HotkeyEvent::Toggled {
+                    id: "undo".to_string(),
+                    time: at(100),
+                },
+                HotkeyEvent::Toggled {
+                    id: "select".to_string(),
+                    time: at(200),
+                },
+                HotkeyEvent::Toggled {
+                    id: "help".to_string(),
+                    time: at(300),
+                },
+            ]
+        );
+        assert!(engine.is_relevant(Key::KeyQ));
+    }
+}
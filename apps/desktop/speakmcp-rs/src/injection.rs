@@ -0,0 +1,72 @@
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use serde::Deserialize;
+
+// Synthetic input reaches the hook asynchronously (XRecord on X11 delivers it
+// after XTest returns), so the window stays open a little after writing.
+const GRACE: Duration = Duration::from_millis(100);
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum InjectedPolicy {
+    // Report with `"injected": true` but keep them away from hotkeys
+    #[default]
+    Tag,
+    // Drop them entirely
+    Suppress,
+}
+
+#[derive(Default)]
+struct State {
+    active: usize,
+    until: Option<SystemTime>,
+}
+
+// Marks the time span in which we are typing ourselves, so the listener can
+// tell our own keystrokes apart from the user's.
+#[derive(Default)]
+pub struct InjectionGuard {
+    state: Mutex<State>,
+}
+
+pub struct InjectionScope<'a> {
+    guard: &'a InjectionGuard,
+}
+
+impl InjectionGuard {
+    pub fn begin(&self) -> InjectionScope<'_> {
+        self.state.lock().unwrap().active += 1;
+        InjectionScope { guard: self }
+    }
+
+    pub fn covers(&self, time: SystemTime) -> bool {
+        let state = self.state.lock().unwrap();
+        state.active > 0 || state.until.is_some_and(|until| time <= until)
+    }
+}
+
+impl Drop for InjectionScope<'_> {
+    fn drop(&mut self) {
+        let mut state = self.guard.state.lock().unwrap();
+        state.active -= 1;
+        state.until = Some(SystemTime::now() + GRACE);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn covers_write_and_grace_period_only() {
+        let guard = InjectionGuard::default();
+        assert!(!guard.covers(SystemTime::now()));
+        {
+            let _scope = guard.begin();
+            assert!(guard.covers(SystemTime::now() + Duration::from_secs(60)));
+        }
+        assert!(guard.covers(SystemTime::now()));
+        assert!(!guard.covers(SystemTime::now() + GRACE * 2));
+    }
+}
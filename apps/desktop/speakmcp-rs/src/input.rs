@@ -0,0 +1,89 @@
+use std::fmt;
+use std::io::Read;
+
+// Generous for transcripts and agent responses while keeping a runaway
+// producer from making us type for hours.
+pub const MAX_INPUT_BYTES: usize = 1024 * 1024;
+
+pub const EXIT_INPUT_TOO_LARGE: i32 = 102;
+pub const EXIT_INVALID_UTF8: i32 = 103;
+pub const EXIT_READ_FAILED: i32 = 104;
+
+#[derive(Debug)]
+pub enum InputError {
+    TooLarge { limit: usize },
+    InvalidUtf8 { valid_up_to: usize },
+    Io(std::io::Error),
+}
+
+impl InputError {
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            InputError::TooLarge { .. } => EXIT_INPUT_TOO_LARGE,
+            InputError::InvalidUtf8 { .. } => EXIT_INVALID_UTF8,
+            InputError::Io(_) => EXIT_READ_FAILED,
+        }
+    }
+}
+
+impl fmt::Display for InputError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            InputError::TooLarge { limit } => {
+                write!(f, "Input exceeds the maximum of {} bytes", limit)
+            }
+            InputError::InvalidUtf8 { valid_up_to } => {
+                write!(f, "Input is not valid UTF-8 (invalid byte at offset {})", valid_up_to)
+            }
+            InputError::Io(e) => write!(f, "Failed to read input: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for InputError {}
+
+pub fn read_text(reader: impl Read, limit: usize) -> Result<String, InputError> {
+    let mut bytes = Vec::new();
+    // One byte more than allowed tells us whether the input was cut off
+    reader
+        .take(limit as u64 + 1)
+        .read_to_end(&mut bytes)
+        .map_err(InputError::Io)?;
+    if bytes.len() > limit {
+        return Err(InputError::TooLarge { limit });
+    }
+
+    String::from_utf8(bytes).map_err(|e| InputError::InvalidUtf8 {
+        valid_up_to: e.utf8_error().valid_up_to(),
+    })
+}
+
+pub fn read_stdin() -> Result<String, InputError> {
+    read_text(std::io::stdin().lock(), MAX_INPUT_BYTES)
+}
+
+pub fn read_file(path: &str) -> Result<String, InputError> {
+    let file = std::fs::File::open(path).map_err(InputError::Io)?;
+    read_text(file, MAX_INPUT_BYTES)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_utf8_within_limit() {
+        let text = read_text("héllo wörld".as_bytes(), 64).unwrap();
+        assert_eq!(text, "héllo wörld");
+    }
+
+    #[test]
+    fn rejects_oversized_and_invalid_input() {
+        let err = read_text("abcdef".as_bytes(), 5).unwrap_err();
+        assert_eq!(err.exit_code(), EXIT_INPUT_TOO_LARGE);
+
+        let err = read_text(&[b'o', b'k', 0xff][..], 64).unwrap_err();
+        assert!(matches!(err, InputError::InvalidUtf8 { valid_up_to: 2 }));
+        assert_eq!(err.exit_code(), EXIT_INVALID_UTF8);
+    }
+}
@@ -0,0 +1,353 @@
+// Key sequence language used by the `keys` command.
+//
+//     sequence := ( text | "{{" | "}}" | "{" command "}" )*
+//     command  := "Sleep" " " milliseconds
+//               | keys [ " " ( "down" | "up" | count ) ]
+//     keys     := key ( "+" key )*
+//     key      := name | single character
+//
+// Plain text is typed as is, `{{` and `}}` produce literal braces. Inside a
+// group, names are case insensitive:
+//
+//     {Enter}          click Enter
+//     {Tab 3}          click Tab three times
+//     {Ctrl+s}         hold Ctrl, click s, release Ctrl
+//     {Ctrl+Shift+t}   modifiers are pressed in order and released in reverse
+//     {Shift down}     press without releasing, `{Shift up}` releases
+//     {Sleep 200}      pause for 200 ms, at most 10000
+//     {+}, {Ctrl++}    the plus key itself
+//
+// Key names: Enter/Return, Tab, Space, Backspace, Delete/Del, Escape/Esc,
+// Up, Down, Left, Right, Home, End, PageUp, PageDown, Insert, CapsLock,
+// F1-F20, Ctrl/Control, Shift, Alt/Option, Meta/Cmd/Super/Win.
+
+use std::fmt;
+use std::time::Duration;
+
+use enigo::{Direction, Key};
+
+use crate::backend::Backend;
+
+// Keeps a typo like `{Tab 10000}` from running away
+const MAX_REPEAT: u32 = 100;
+// Sleeps block the daemon and cancelWrite doesn't end them
+const MAX_SLEEP_MS: u64 = 10_000;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Action {
+    Text(String),
+    Key(Key, Direction),
+    Sleep(Duration),
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    // 1-based character column in the input
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "column {}: {}", self.column, self.message)
+    }
+}
+
+impl std::error::Error for ParseError {}
+
+fn error(column: usize, message: impl Into<String>) -> ParseError {
+    ParseError {
+        column,
+        message: message.into(),
+    }
+}
+
+// Modifier of the common shortcuts (select all, undo, ...) in key sequence
+// syntax
+pub fn primary_modifier() -> &'static str {
+    if cfg!(target_os = "macos") {
+        "Meta"
+    } else {
+        "Ctrl"
+    }
+}
+
+pub fn key_from_name(name: &str) -> Option<Key> {
+    let mut chars = name.chars();
+    if let (Some(c), None) = (chars.next(), chars.next()) {
+        return Some(Key::Unicode(c));
+    }
+
+    let key = match name.to_lowercase().as_str() {
+        "enter" | "return" => Key::Return,
+        "tab" => Key::Tab,
+        "space" => Key::Space,
+        "backspace" => Key::Backspace,
+        "delete" | "del" => Key::Delete,
+        "escape" | "esc" => Key::Escape,
+        "up" => Key::UpArrow,
+        "down" => Key::DownArrow,
+        "left" => Key::LeftArrow,
+        "right" => Key::RightArrow,
+        "home" => Key::Home,
+        "end" => Key::End,
+        "pageup" => Key::PageUp,
+        "pagedown" => Key::PageDown,
+        #[cfg(not(target_os = "macos"))]
+        "insert" => Key::Insert,
+        "capslock" => Key::CapsLock,
+        "ctrl" | "control" => Key::Control,
+        "shift" => Key::Shift,
+        "alt" | "option" => Key::Alt,
+        "meta" | "cmd" | "command" | "super" | "win" => Key::Meta,
+        "f1" => Key::F1,
+        "f2" => Key::F2,
+        "f3" => Key::F3,
+        "f4" => Key::F4,
+        "f5" => Key::F5,
+        "f6" => Key::F6,
+        "f7" => Key::F7,
+        "f8" => Key::F8,
+        "f9" => Key::F9,
+        "f10" => Key::F10,
+        "f11" => Key::F11,
+        "f12" => Key::F12,
+        "f13" => Key::F13,
+        "f14" => Key::F14,
+        "f15" => Key::F15,
+        "f16" => Key::F16,
+        "f17" => Key::F17,
+        "f18" => Key::F18,
+        "f19" => Key::F19,
+        "f20" => Key::F20,
+        _ => return None,
+    };
+    Some(key)
+}
+
+// Splits `Ctrl+Shift+t` into (column, name) pairs, treating a trailing `++`
+// or a lone `+` as the plus key.
+fn split_keys(keys: &str, column: usize) -> Result<Vec<(usize, &str)>, ParseError> {
+    if keys == "+" {
+        return Ok(vec![(column, "+")]);
+    }
+    let (body, plus) = match keys.strip_suffix("++") {
+        Some(body) => (body, true),
+        None => (keys, false),
+    };
+
+    let mut names = Vec::new();
+    let mut offset = 0;
+    for name in body.split('+') {
+        if name.is_empty() {
+            return Err(error(column + offset, "expected a key name"));
+        }
+        names.push((column + offset, name));
+        offset += name.chars().count() + 1;
+    }
+    if plus {
+        names.push((column + offset, "+"));
+    }
+    Ok(names)
+}
+
+fn parse_group(group: &str, column: usize, actions: &mut Vec<Action>) -> Result<(), ParseError> {
+    if group.trim().is_empty() {
+        return Err(error(column - 1, "empty {} group"));
+    }
+    if group != group.trim() {
+        return Err(error(column, "unexpected whitespace"));
+    }
+
+    let (keys, argument) = match group.split_once(' ') {
+        Some((keys, argument)) => (keys, Some(argument)),
+        None => (group, None),
+    };
+    let argument_column = column + keys.chars().count() + 1;
+
+    if keys.eq_ignore_ascii_case("sleep") {
+        let ms = argument
+            .ok_or_else(|| error(column, "Sleep needs a duration in milliseconds"))?
+            .parse::<u64>()
+            .ok()
+            .filter(|ms| *ms <= MAX_SLEEP_MS)
+            .ok_or_else(|| {
+                error(
+                    argument_column,
+                    format!("expected a Sleep duration from 0 to {} ms", MAX_SLEEP_MS),
+                )
+            })?;
+        actions.push(Action::Sleep(Duration::from_millis(ms)));
+        return Ok(());
+    }
+
+    let keys = split_keys(keys, column)?
+        .into_iter()
+        .map(|(column, name)| {
+            key_from_name(name).ok_or_else(|| error(column, format!("unknown key '{}'", name)))
+        })
+        .collect::<Result<Vec<Key>, ParseError>>()?;
+
+    match argument {
+        Some(argument) if argument.eq_ignore_ascii_case("down") => {
+            actions.extend(keys.iter().map(|key| Action::Key(*key, Direction::Press)));
+        }
+        Some(argument) if argument.eq_ignore_ascii_case("up") => {
+            actions.extend(keys.iter().rev().map(|key| Action::Key(*key, Direction::Release)));
+        }
+        argument => {
+            let count = match argument {
+                Some(argument) => argument
+                    .parse::<u32>()
+                    .ok()
+                    .filter(|count| (1..=MAX_REPEAT).contains(count))
+                    .ok_or_else(|| {
+                        error(
+                            argument_column,
+                            format!(
+                                "expected down, up or a repeat count from 1 to {}",
+                                MAX_REPEAT
+                            ),
+                        )
+                    })?,
+                None => 1,
+            };
+            let (key, modifiers) = keys.split_last().unwrap();
+            for _ in 0..count {
+                actions.extend(modifiers.iter().map(|m| Action::Key(*m, Direction::Press)));
+                actions.push(Action::Key(*key, Direction::Click));
+                actions.extend(modifiers.iter().rev().map(|m| Action::Key(*m, Direction::Release)));
+            }
+        }
+    }
+    Ok(())
+}
+
+pub fn parse(input: &str) -> Result<Vec<Action>, ParseError> {
+    let mut actions = Vec::new();
+    let mut text = String::new();
+    let chars: Vec<char> = input.chars().collect();
+    let mut i = 0;
+
+    while i < chars.len() {
+        let column = i + 1;
+        match chars[i] {
+            '{' if chars.get(i + 1) == Some(&'{') => {
+                text.push('{');
+                i += 2;
+            }
+            '}' if chars.get(i + 1) == Some(&'}') => {
+                text.push('}');
+                i += 2;
+            }
+            '}' => return Err(error(column, "unexpected '}', use '}}' for a literal brace")),
+            '{' => {
+                let end = chars[i + 1..]
+                    .iter()
+                    .position(|c| *c == '}')
+                    .map(|offset| i + 1 + offset)
+                    .ok_or_else(|| error(column, "unterminated '{'"))?;
+                let group: String = chars[i + 1..end].iter().collect();
+                if !text.is_empty() {
+                    actions.push(Action::Text(std::mem::take(&mut text)));
+                }
+                parse_group(&group, column + 1, &mut actions)?;
+                i = end + 1;
+            }
+            c => {
+                text.push(c);
+                i += 1;
+            }
+        }
+    }
+    if !text.is_empty() {
+        actions.push(Action::Text(text));
+    }
+    Ok(actions)
+}
+
+pub fn execute(backend: &mut dyn Backend, actions: &[Action]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut held: Vec<Key> = Vec::new();
+    let mut result = Ok(());
+
+    for action in actions {
+        let step = match action {
+            Action::Text(text) => backend.text(text),
+            Action::Key(key, direction) => {
+                match direction {
+                    Direction::Press => held.push(*key),
+                    Direction::Release => held.retain(|k| k != key),
+                    Direction::Click => {}
+                }
+                backend.key(*key, *direction)
+            }
+            Action::Sleep(duration) => {
+                std::thread::sleep(*duration);
+                Ok(())
+            }
+        };
+        if let Err(e) = step {
+            result = Err(format!("Failed to send keys with the {} backend: {}", backend.name(), e).into());
+            break;
+        }
+    }
+
+    // Never leave keys pressed behind, whether the sequence forgot to release
+    // them or failed halfway.
+    for key in held.into_iter().rev() {
+        if let Err(e) = backend.key(key, Direction::Release) {
+            eprintln!("Failed to release {:?}: {}", key, e);
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_text_keys_chords_and_pauses() {
+        let actions = parse("hi{Enter}{Ctrl+s}{Sleep 200}{{x}}").unwrap();
+        assert_eq!(
+            actions,
+            vec![
+                Action::Text("hi".to_string()),
+                Action::Key(Key::Return, Direction::Click),
+                Action::Key(Key::Control, Direction::Press),
+                Action::Key(Key::Unicode('s'), Direction::Click),
+                Action::Key(Key::Control, Direction::Release),
+                Action::Sleep(Duration::from_millis(200)),
+                Action::Text("{x}".to_string()),
+            ]
+        );
+    }
+
+    #[test]
+    fn parses_directions_repeats_and_plus() {
+        assert_eq!(
+            parse("{Shift down}{shift up}").unwrap(),
+            vec![
+                Action::Key(Key::Shift, Direction::Press),
+                Action::Key(Key::Shift, Direction::Release),
+            ]
+        );
+        assert_eq!(parse("{Tab 3}").unwrap().len(), 3);
+        assert_eq!(
+            parse("{Ctrl++}").unwrap()[1],
+            Action::Key(Key::Unicode('+'), Direction::Click)
+        );
+    }
+
+    #[test]
+    fn reports_error_columns() {
+        assert_eq!(parse("ab{Enter").unwrap_err().column, 3);
+        assert_eq!(parse("ab}c").unwrap_err().column, 3);
+        let err = parse("x{Ctrl+Foo}").unwrap_err();
+        assert_eq!((err.column, err.message.as_str()), (8, "unknown key 'Foo'"));
+        assert_eq!(parse("{Sleep soon}").unwrap_err().column, 8);
+        assert_eq!(parse("{Sleep 4000000000}").unwrap_err().column, 8);
+        assert!(parse("{Sleep 10000}").is_ok());
+        assert_eq!(parse("{Ctrl+}").unwrap_err().column, 7);
+    }
+}
@@ -0,0 +1,128 @@
+use std::collections::HashMap;
+use std::time::{Duration, SystemTime};
+
+use rdev::Key;
+use serde::Serialize;
+
+use crate::hotkey::{is_modifier, Modifiers};
+
+pub const DEFAULT_STUCK_KEY_TIMEOUT_MS: u64 = 10_000;
+
+#[derive(Serialize, Debug, Clone, PartialEq)]
+pub struct KeySnapshot {
+    pub keys: Vec<String>,
+    pub modifiers: Modifiers,
+}
+
+// Tracks which keys are physically down so missing KeyRelease events can be
+// made up for instead of leaving keys stuck forever.
+pub struct KeyState {
+    // Last press (including auto-repeat) of every held key
+    pressed: HashMap<Key, SystemTime>,
+    // Non-modifier keys held longer than this without auto-repeat are
+    // considered stuck. Modifiers never repeat on some platforms and are held
+    // for the whole recording, so they are exempt.
+    timeout: Duration,
+}
+
+impl Default for KeyState {
+    fn default() -> Self {
+        KeyState::new(Duration::from_millis(DEFAULT_STUCK_KEY_TIMEOUT_MS))
+    }
+}
+
+impl KeyState {
+    pub fn new(timeout: Duration) -> Self {
+        KeyState {
+            pressed: HashMap::new(),
+            timeout,
+        }
+    }
+
+    pub fn set_timeout(&mut self, timeout: Duration) {
+        self.timeout = timeout;
+    }
+
+    pub fn press(&mut self, key: Key, time: SystemTime) {
+        self.pressed.insert(key, time);
+    }
+
+    pub fn is_pressed(&self, key: Key) -> bool {
+        self.pressed.contains_key(&key)
+    }
+
+    pub fn release(&mut self, key: Key) {
+        self.pressed.remove(&key);
+    }
+
+    // Removes and returns keys that should have been released by now
+    pub fn expire(&mut self, now: SystemTime) -> Vec<Key> {
+        let timeout = self.timeout;
+        self.take(|key, time| {
+            !is_modifier(key)
+                && now
+                    .duration_since(time)
+                    .is_ok_and(|held| held >= timeout)
+        })
+    }
+
+    // On focus loss the release of ordinary keys goes elsewhere; modifiers
+    // are kept since they are typically still held across the switch.
+    pub fn release_non_modifiers(&mut self) -> Vec<Key> {
+        self.take(|key, _| !is_modifier(key))
+    }
+
+    pub fn release_all(&mut self) -> Vec<Key> {
+        self.take(|_, _| true)
+    }
+
+    fn take(&mut self, pred: impl Fn(Key, SystemTime) -> bool) -> Vec<Key> {
+        let mut keys: Vec<Key> = self
+            .pressed
+            .iter()
+            .filter(|(key, time)| pred(**key, **time))
+            .map(|(key, _)| *key)
+            .collect();
+        keys.sort_by_key(|key| format!("{:?}", key));
+        for key in &keys {
+            self.pressed.remove(key);
+        }
+        keys
+    }
+
+    pub fn snapshot(&self, name: impl Fn(Key) -> String) -> KeySnapshot {
+        let mut keys: Vec<String> = self.pressed.keys().map(|key| name(*key)).collect();
+        keys.sort();
+        keys.dedup();
+        KeySnapshot {
+            keys,
+            modifiers: Modifiers::from_keys(self.pressed.keys()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    #[test]
+    fn expires_only_silent_non_modifier_keys() {
+        let mut state = KeyState::new(Duration::from_millis(1000));
+        state.press(Key::ControlLeft, at(0));
+        state.press(Key::KeyA, at(0));
+        state.press(Key::KeyB, at(0));
+        // Auto-repeat keeps B alive
+        state.press(Key::KeyB, at(900));
+
+        assert_eq!(state.expire(at(1500)), vec![Key::KeyA]);
+        assert_eq!(state.expire(at(2000)), vec![Key::KeyB]);
+
+        let snapshot = state.snapshot(|key| format!("{:?}", key));
+        assert_eq!(snapshot.keys, vec!["ControlLeft".to_string()]);
+        assert!(snapshot.modifiers.ctrl);
+    }
+}
@@ -0,0 +1,191 @@
+use std::collections::{HashMap, HashSet};
+
+use rdev::{Event, EventType, Key};
+use serde::Serialize;
+
+use crate::hotkey::is_modifier;
+
+#[cfg(target_os = "linux")]
+mod xkb;
+
+// rdev names keys after their place on a US keyboard. This is what else we
+// know about a key event: the physical key and what it types on the
+// user's layout.
+#[derive(Serialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct KeyInfo {
+    // evdev code of the physical key (KEY_Q is 16), the same whatever the
+    // layout. Not the hardware scancode, which the kernel maps to this.
+    pub evdev_code: Option<u16>,
+    // What the platform calls the key: the X11 keycode on Linux, the
+    // virtual key or key code elsewhere (only known for keys rdev can't name)
+    pub keycode: Option<u32>,
+    // The character the key types without modifiers, None for keys that
+    // don't type one (Shift, F1, dead keys)
+    pub char: Option<char>,
+}
+
+macro_rules! decl_keycodes {
+    ($($code:literal => $key:ident),* $(,)?) => {
+        // Same keys rdev reports under X11, whose keycodes are the evdev
+        // codes plus 8. Keys rdev has no name for become Unknown with the
+        // X11 keycode.
+        #[cfg(target_os = "linux")]
+        pub fn key_from_code(code: u16) -> Key {
+            match code {
+                $($code => Key::$key,)*
+                _ => Key::Unknown(code as u32 + 8),
+            }
+        }
+
+        pub fn code_from_key(key: Key) -> Option<u16> {
+            match key {
+                $(Key::$key => Some($code),)*
+                #[cfg(target_os = "linux")]
+                Key::Unknown(code) => u16::try_from(code).ok()?.checked_sub(8),
+                _ => None,
+            }
+        }
+    };
+}
+
+#[rustfmt::skip]
+decl_keycodes!(
+    1 => Escape,
+    2 => Num1, 3 => Num2, 4 => Num3, 5 => Num4, 6 => Num5,
+    7 => Num6, 8 => Num7, 9 => Num8, 10 => Num9, 11 => Num0,
+    12 => Minus, 13 => Equal, 14 => Backspace, 15 => Tab,
+    16 => KeyQ, 17 => KeyW, 18 => KeyE, 19 => KeyR, 20 => KeyT,
+    21 => KeyY, 22 => KeyU, 23 => KeyI, 24 => KeyO, 25 => KeyP,
+    26 => LeftBracket, 27 => RightBracket, 28 => Return, 29 => ControlLeft,
+    30 => KeyA, 31 => KeyS, 32 => KeyD, 33 => KeyF, 34 => KeyG,
+    35 => KeyH, 36 => KeyJ, 37 => KeyK, 38 => KeyL,
+    39 => SemiColon, 40 => Quote, 41 => BackQuote, 42 => ShiftLeft, 43 => BackSlash,
+    44 => KeyZ, 45 => KeyX, 46 => KeyC, 47 => KeyV, 48 => KeyB,
+    49 => KeyN, 50 => KeyM,
+    51 => Comma, 52 => Dot, 53 => Slash, 54 => ShiftRight, 55 => KpMultiply,
+    56 => Alt, 57 => Space, 58 => CapsLock,
+    59 => F1, 60 => F2, 61 => F3, 62 => F4, 63 => F5,
+    64 => F6, 65 => F7, 66 => F8, 67 => F9, 68 => F10,
+    69 => NumLock, 70 => ScrollLock,
+    71 => Kp7, 72 => Kp8, 73 => Kp9, 74 => KpMinus,
+    75 => Kp4, 76 => Kp5, 77 => Kp6, 78 => KpPlus,
+    79 => Kp1, 80 => Kp2, 81 => Kp3, 82 => Kp0, 83 => KpDelete,
+    86 => IntlBackslash, 87 => F11, 88 => F12,
+    96 => KpReturn, 97 => ControlRight, 98 => KpDivide, 99 => PrintScreen, 100 => AltGr,
+    102 => Home, 103 => UpArrow, 104 => PageUp, 105 => LeftArrow,
+    106 => RightArrow, 107 => End, 108 => DownArrow, 109 => PageDown,
+    110 => Insert, 111 => Delete, 119 => Pause, 125 => MetaLeft,
+);
+
+// Resolves key events to `KeyInfo`. Characters come from the XKB keymap of
+// the session when libxkbcommon is around, otherwise from the names rdev
+// reports for presses without modifiers.
+#[derive(Default)]
+pub struct Layout {
+    #[cfg(target_os = "linux")]
+    xkb: Option<xkb::Xkb>,
+    // Characters learned from rdev's names
+    learned: HashMap<Key, char>,
+    modifiers: HashSet<Key>,
+}
+
+impl Layout {
+    pub fn detect() -> Self {
+        Layout {
+            #[cfg(target_os = "linux")]
+            xkb: xkb::Xkb::detect()
+                .map_err(|e| eprintln!("Key characters come from rdev only: {}", e))
+                .ok(),
+            ..Layout::default()
+        }
+    }
+
+    // None for anything but key events
+    pub fn key_info(&mut self, event: &Event) -> Option<KeyInfo> {
+        let (key, pressed) = match event.event_type {
+            EventType::KeyPress(key) => (key, true),
+            EventType::KeyRelease(key) => (key, false),
+            _ => return None,
+        };
+        let evdev_code = code_from_key(key);
+        let keycode = if cfg!(target_os = "linux") {
+            evdev_code.map(|code| code as u32 + 8)
+        } else {
+            match key {
+                Key::Unknown(code) => Some(code),
+                _ => None,
+            }
+        };
+
+        if is_modifier(key) {
+            if pressed {
+                self.modifiers.insert(key);
+            } else {
+                self.modifiers.remove(&key);
+            }
+        } else if pressed && self.modifiers.is_empty() {
+            // Shift and AltGr change the name, so only learn from plain
+            // presses
+            let mut chars = event.name.iter().flat_map(|name| name.chars());
+            if let (Some(c), None) = (chars.next(), chars.next()) {
+                if !c.is_control() {
+                    self.learned
+                        .insert(key, c.to_lowercase().next().unwrap_or(c));
+                }
+            }
+        }
+
+        #[cfg(target_os = "linux")]
+        if let (Some(xkb), Some(code)) = (&mut self.xkb, evdev_code) {
+            xkb.update(code, pressed);
+            return Some(KeyInfo {
+                evdev_code,
+                keycode,
+                char: xkb.base_char(code),
+            });
+        }
+        Some(KeyInfo {
+            evdev_code,
+            keycode,
+            char: self.learned.get(&key).copied(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::SystemTime;
+
+    fn event(event_type: EventType, name: Option<&str>) -> Event {
+        Event {
+            time: SystemTime::UNIX_EPOCH,
+            name: name.map(str::to_string),
+            event_type,
+        }
+    }
+
+    #[test]
+    fn learns_characters_from_plain_presses() {
+        let mut layout = Layout::default();
+        let info = layout.key_info(&event(EventType::KeyPress(Key::KeyQ), Some("a")));
+        assert_eq!(info.unwrap().char, Some('a'));
+        if cfg!(target_os = "linux") {
+            assert_eq!(info.unwrap().evdev_code, Some(16));
+            assert_eq!(info.unwrap().keycode, Some(24));
+        }
+
+        // Shifted presses don't overwrite it, releases keep it
+        layout.key_info(&event(EventType::KeyPress(Key::ShiftLeft), None));
+        layout.key_info(&event(EventType::KeyPress(Key::KeyQ), Some("A")));
+        layout.key_info(&event(EventType::KeyPress(Key::Num1), Some("1")));
+        layout.key_info(&event(EventType::KeyRelease(Key::ShiftLeft), None));
+        let info = layout.key_info(&event(EventType::KeyRelease(Key::KeyQ), None));
+        assert_eq!(info.unwrap().char, Some('a'));
+        let info = layout.key_info(&event(EventType::KeyPress(Key::Num1), Some("\u{1}")));
+        assert_eq!(info.unwrap().char, None);
+        assert!(layout
+            .key_info(&event(EventType::ButtonPress(rdev::Button::Left), None))
+            .is_none());
+    }
+}
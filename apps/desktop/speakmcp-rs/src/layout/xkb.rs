@@ -0,0 +1,204 @@
+use std::ffi::CString;
+use std::ptr;
+
+use xkbcommon_dl::{
+    xkb_context, xkb_context_flags, xkb_key_direction, xkb_keymap, xkb_keymap_compile_flags,
+    xkb_rule_names, xkb_state, xkbcommon_option, XkbCommon,
+};
+
+// RMLVO names of an XKB keymap. Empty fields take libxkbcommon's defaults,
+// which honor XKB_DEFAULT_LAYOUT and friends.
+#[derive(Debug, Default, PartialEq)]
+pub struct Names {
+    pub rules: String,
+    pub model: String,
+    pub layout: String,
+    pub variant: String,
+    pub options: String,
+}
+
+// The names the X server (or XWayland) compiled its keymap from, kept in
+// the _XKB_RULES_NAMES root window property as NUL separated strings.
+fn x11_names() -> Option<Names> {
+    use x11rb::connection::Connection;
+    use x11rb::protocol::xproto::{AtomEnum, ConnectionExt};
+
+    let (conn, screen) = x11rb::connect(None).ok()?;
+    let root = conn.setup().roots.get(screen)?.root;
+    let atom = conn
+        .intern_atom(true, b"_XKB_RULES_NAMES")
+        .ok()?
+        .reply()
+        .ok()?
+        .atom;
+    let reply = conn
+        .get_property(false, root, atom, AtomEnum::STRING, 0, 1024)
+        .ok()?
+        .reply()
+        .ok()?;
+    let mut fields = reply
+        .value
+        .split(|byte| *byte == 0)
+        .map(|field| String::from_utf8_lossy(field).into_owned());
+    Some(Names {
+        rules: fields.next()?,
+        model: fields.next().unwrap_or_default(),
+        layout: fields.next().unwrap_or_default(),
+        variant: fields.next().unwrap_or_default(),
+        options: fields.next().unwrap_or_default(),
+    })
+}
+
+// An XKB keymap and its state, which follows layout switches made with
+// the keys we see.
+pub struct Xkb {
+    lib: &'static XkbCommon,
+    context: *mut xkb_context,
+    keymap: *mut xkb_keymap,
+    state: *mut xkb_state,
+}
+
+// Only ever used behind the listener's lock
+unsafe impl Send for Xkb {}
+
+impl Xkb {
+    // The session's keymap as far as we can tell: the X server's names,
+    // or libxkbcommon's defaults on Wayland and the console
+    pub fn detect() -> Result<Self, String> {
+        Xkb::from_names(&x11_names().unwrap_or_default())
+    }
+
+    pub fn from_names(names: &Names) -> Result<Self, String> {
+        let lib = xkbcommon_option().ok_or("libxkbcommon is not installed")?;
+        let field = |value: &str| CString::new(value).map_err(|e| e.to_string());
+        let (rules, model, layout, variant, options) = (
+            field(&names.rules)?,
+            field(&names.model)?,
+            field(&names.layout)?,
+            field(&names.variant)?,
+            field(&names.options)?,
+        );
+        let nullable = |value: &CString| {
+            if value.as_bytes().is_empty() {
+                ptr::null()
+            } else {
+                value.as_ptr()
+            }
+        };
+        let rule_names = xkb_rule_names {
+            rules: nullable(&rules),
+            model: nullable(&model),
+            layout: nullable(&layout),
+            variant: nullable(&variant),
+            options: nullable(&options),
+        };
+
+        unsafe {
+            let context = (lib.xkb_context_new)(xkb_context_flags::XKB_CONTEXT_NO_FLAGS);
+            if context.is_null() {
+                return Err("can't create an XKB context".to_string());
+            }
+            let keymap = (lib.xkb_keymap_new_from_names)(
+                context,
+                &rule_names,
+                xkb_keymap_compile_flags::XKB_KEYMAP_COMPILE_NO_FLAGS,
+            );
+            if keymap.is_null() {
+                (lib.xkb_context_unref)(context);
+                return Err(format!("can't compile the XKB keymap {:?}", names));
+            }
+            let state = (lib.xkb_state_new)(keymap);
+            if state.is_null() {
+                (lib.xkb_keymap_unref)(keymap);
+                (lib.xkb_context_unref)(context);
+                return Err("can't create an XKB state".to_string());
+            }
+            Ok(Xkb {
+                lib,
+                context,
+                keymap,
+                state,
+            })
+        }
+    }
+
+    pub fn update(&mut self, code: u16, pressed: bool) {
+        let direction = if pressed {
+            xkb_key_direction::XKB_KEY_DOWN
+        } else {
+            xkb_key_direction::XKB_KEY_UP
+        };
+        unsafe {
+            (self.lib.xkb_state_update_key)(self.state, code as u32 + 8, direction);
+        }
+    }
+
+    // What the key types in the active layout at the first level, the
+    // character printed on it
+    pub fn base_char(&self, code: u16) -> Option<char> {
+        let keycode = code as u32 + 8;
+        let mut syms = ptr::null();
+        let c = unsafe {
+            let layout = (self.lib.xkb_state_key_get_layout)(self.state, keycode);
+            let count = (self.lib.xkb_keymap_key_get_syms_by_level)(
+                self.keymap,
+                keycode,
+                layout,
+                0,
+                &mut syms,
+            );
+            if count != 1 {
+                return None;
+            }
+            (self.lib.xkb_keysym_to_utf32)(*syms)
+        };
+        char::from_u32(c).filter(|c| *c != '\0' && !c.is_control())
+    }
+}
+
+impl Drop for Xkb {
+    fn drop(&mut self) {
+        unsafe {
+            (self.lib.xkb_state_unref)(self.state);
+            (self.lib.xkb_keymap_unref)(self.keymap);
+            (self.lib.xkb_context_unref)(self.context);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Needs libxkbcommon and xkeyboard-config with the fr layout and the
+    // dvorak variant: `cargo test -- --ignored`
+    #[test]
+    #[ignore]
+    fn resolves_characters_of_the_layout() {
+        let names = |layout: &str| Names {
+            rules: "evdev".to_string(),
+            layout: layout.to_string(),
+            ..Names::default()
+        };
+        let azerty = Xkb::from_names(&names("fr")).unwrap();
+        // KEY_Q, KEY_A, KEY_1 and KEY_F1
+        assert_eq!(azerty.base_char(16), Some('a'));
+        assert_eq!(azerty.base_char(30), Some('q'));
+        assert_eq!(azerty.base_char(2), Some('&'));
+        assert_eq!(azerty.base_char(59), None);
+
+        // Shift+Alt switches to the second layout, Q types ' then
+        let mut dvorak = Xkb::from_names(&Names {
+            variant: ",dvorak".to_string(),
+            options: "grp:alt_shift_toggle".to_string(),
+            ..names("us,us")
+        })
+        .unwrap();
+        assert_eq!(dvorak.base_char(16), Some('q'));
+        dvorak.update(42, true);
+        dvorak.update(56, true);
+        dvorak.update(56, false);
+        dvorak.update(42, false);
+        assert_eq!(dvorak.base_char(16), Some('\''));
+    }
+}
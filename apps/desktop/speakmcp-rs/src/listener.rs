@@ -0,0 +1,346 @@
+use std::io::BufRead;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+
+use rdev::{Event, EventType, Key};
+use serde_json::Value;
+
+use crate::backend::BackendError;
+use crate::capture::{self, CaptureKind};
+use crate::event::{event_to_value, other_key_to_value, Format, OTHER_KEY};
+use crate::focus;
+use crate::grab::Grab;
+use crate::hotkey::{HotkeyEngine, HotkeyEvent};
+use crate::injection::{InjectedPolicy, InjectionGuard};
+use crate::keystate::{KeySnapshot, KeyState};
+use crate::layout::{KeyInfo, Layout};
+
+const TICK_INTERVAL: Duration = Duration::from_millis(10);
+const FOCUS_POLL_INTERVAL: Duration = Duration::from_millis(500);
+pub const DEFAULT_MOUSE_MOVE_INTERVAL_MS: u64 = 50;
+
+pub enum Output {
+    Event(Value),
+    Hotkey(HotkeyEvent),
+    KeyState(KeySnapshot),
+}
+
+// Shared by `listen` and the daemon: turns raw rdev events into the lines
+// we print, including high level hotkey events.
+#[derive(Default)]
+pub struct Listener {
+    // Format of raw key events, None mutes them while hotkeys keep working
+    pub format: Option<Format>,
+    pub hotkeys: HotkeyEngine,
+    // Only name modifiers and shortcut keys, everything else is reported as
+    // an anonymous key so typed text never leaves the process.
+    pub private: bool,
+    pub keys: KeyState,
+    // Release held keys when the focused window changes
+    pub release_on_focus_change: bool,
+    focused_window: Option<u64>,
+    // Shared with whoever types so our own keystrokes can be recognized
+    pub injection: Arc<InjectionGuard>,
+    pub injected: InjectedPolicy,
+    // Set when the keyboard is grabbed, decides what the focused
+    // application still gets
+    pub grab: Option<Grab>,
+    // Report mouse events, with at most one move per interval. Buttons
+    // trigger hotkeys either way.
+    pub mouse: Option<Duration>,
+    last_move: Option<SystemTime>,
+    pending_move: Option<Event>,
+    // Resolves evdev codes and layout characters of key events
+    pub layout: Layout,
+}
+
+impl Listener {
+    pub fn process(&mut self, event: Event) -> Vec<Output> {
+        // Our own keystrokes switch layouts too
+        let key_info = self.layout.key_info(&event);
+        if self.is_injected(&event) {
+            if let Some(grab) = &mut self.grab {
+                grab.pass(event.event_type);
+            }
+            let mut out = self.tick(event.time);
+            if self.injected == InjectedPolicy::Tag {
+                if let Some(Output::Event(mut value)) = self.raw(event, key_info) {
+                    value["injected"] = Value::Bool(true);
+                    out.push(Output::Event(value));
+                }
+            }
+            return out;
+        }
+
+        match event.event_type {
+            EventType::KeyPress(key) => {
+                self.keys.press(key, event.time);
+                self.hotkeys.set_char(key, key_info.and_then(|info| info.char));
+            }
+            EventType::KeyRelease(key) => self.keys.release(key),
+            EventType::MouseMove { .. } => return self.mouse_move(event),
+            _ => {}
+        }
+
+        // Holds that matured before this event, then the raw event, then
+        // whatever it triggered
+        let mut out = self.tick(event.time);
+        let hotkeys = self.hotkeys.handle(&event);
+        if let Some(grab) = &mut self.grab {
+            grab.confirm(&hotkeys);
+            grab.filter(&event, &self.hotkeys);
+        }
+        out.extend(self.raw(event, key_info));
+        out.extend(hotkeys.into_iter().map(Output::Hotkey));
+        out
+    }
+
+    // Key events seen while we are typing are ours, except for releases of
+    // keys the user was already holding, which must not get lost.
+    fn is_injected(&self, event: &Event) -> bool {
+        match event.event_type {
+            EventType::KeyPress(_) => self.injection.covers(event.time),
+            EventType::KeyRelease(key) => {
+                !self.keys.is_pressed(key) && self.injection.covers(event.time)
+            }
+            _ => false,
+        }
+    }
+
+    // Moves are throttled to one per interval. The last one held back is
+    // reported by `tick`, so the final position is never lost.
+    fn mouse_move(&mut self, event: Event) -> Vec<Output> {
+        self.pending_move = None;
+        let mut out = self.tick(event.time);
+        let Some(interval) = self.mouse else {
+            return out;
+        };
+        if self.last_move.is_some_and(|last| event.time < last + interval) {
+            self.pending_move = Some(event);
+        } else {
+            self.last_move = Some(event.time);
+            out.extend(self.raw(event, None));
+        }
+        out
+    }
+
+    fn raw(&self, mut event: Event, key_info: Option<KeyInfo>) -> Option<Output> {
+        let format = self.format?;
+        let key = match event.event_type {
+            EventType::KeyPress(key) | EventType::KeyRelease(key) => key,
+            _ if self.mouse.is_some() => {
+                return Some(Output::Event(event_to_value(event, format, None)))
+            }
+            _ => return None,
+        };
+
+        if !self.private {
+            Some(Output::Event(event_to_value(event, format, key_info)))
+        } else if self.hotkeys.is_relevant(key) {
+            event.name = None;
+            Some(Output::Event(event_to_value(event, format, key_info)))
+        } else {
+            other_key_to_value(&event, format).map(Output::Event)
+        }
+    }
+
+    fn key_name(&self, key: Key) -> String {
+        if self.private && !self.hotkeys.is_relevant(key) {
+            OTHER_KEY.to_string()
+        } else {
+            format!("{:?}", key)
+        }
+    }
+
+    // Feeds made up KeyRelease events through the same path as real ones,
+    // marked with `"synthetic": true`.
+    fn synthesize_releases(&mut self, keys: Vec<Key>, now: SystemTime) -> Vec<Output> {
+        let mut out = Vec::new();
+        for key in keys {
+            let event = Event {
+                time: now,
+                name: None,
+                event_type: EventType::KeyRelease(key),
+            };
+            let key_info = self.layout.key_info(&event);
+            let hotkeys = self.hotkeys.handle(&event);
+            if let Some(Output::Event(mut value)) = self.raw(event, key_info) {
+                value["synthetic"] = Value::Bool(true);
+                out.push(Output::Event(value));
+            }
+            out.extend(hotkeys.into_iter().map(Output::Hotkey));
+        }
+        out
+    }
+
+    pub fn tick(&mut self, now: SystemTime) -> Vec<Output> {
+        let hotkeys = self.hotkeys.tick(now);
+        if let Some(grab) = &mut self.grab {
+            grab.confirm(&hotkeys);
+        }
+        let mut out: Vec<Output> = hotkeys.into_iter().map(Output::Hotkey).collect();
+        let stuck = self.keys.expire(now);
+        out.extend(self.synthesize_releases(stuck, now));
+
+        if let (Some(interval), Some(last)) = (self.mouse, self.last_move) {
+            if now >= last + interval {
+                if let Some(event) = self.pending_move.take() {
+                    self.last_move = Some(now);
+                    out.extend(self.raw(event, None));
+                }
+            }
+        }
+        out
+    }
+
+    pub fn focus_changed(&mut self, window: Option<u64>, now: SystemTime) -> Vec<Output> {
+        let previous = std::mem::replace(&mut self.focused_window, window);
+        if previous.is_none() || previous == window {
+            return Vec::new();
+        }
+        let keys = self.keys.release_non_modifiers();
+        self.synthesize_releases(keys, now)
+    }
+
+    pub fn reset(&mut self, now: SystemTime) -> Vec<Output> {
+        let keys = self.keys.release_all();
+        self.synthesize_releases(keys, now)
+    }
+
+    // Key events the focused application should get while grabbing
+    pub fn take_forwarded(&mut self) -> Vec<EventType> {
+        self.grab.as_mut().map(Grab::take).unwrap_or_default()
+    }
+
+    pub fn snapshot(&self) -> KeySnapshot {
+        self.keys.snapshot(|key| self.key_name(key))
+    }
+}
+
+// Blocks for as long as the listener backend is running. A listener with
+// `grab` set takes the keyboards for itself.
+pub fn run<F>(
+    listener: Arc<Mutex<Listener>>,
+    kind: Option<CaptureKind>,
+    emit: F,
+) -> Result<(), BackendError>
+where
+    F: Fn(Output) + Clone + Send + 'static,
+{
+    let grab = {
+        let mut listener = listener.lock().unwrap();
+        listener.layout = Layout::detect();
+        listener.grab.is_some()
+    };
+    let ticker = listener.clone();
+    let emit_tick = emit.clone();
+    std::thread::spawn(move || {
+        let mut last_focus_poll = SystemTime::UNIX_EPOCH;
+        loop {
+            std::thread::sleep(TICK_INTERVAL);
+            let now = SystemTime::now();
+            let (mut out, watch_focus) = {
+                let mut listener = ticker.lock().unwrap();
+                (listener.tick(now), listener.release_on_focus_change)
+            };
+
+            if watch_focus
+                && now
+                    .duration_since(last_focus_poll)
+                    .is_ok_and(|elapsed| elapsed >= FOCUS_POLL_INTERVAL)
+            {
+                last_focus_poll = now;
+                // Queried without holding the lock, it talks to the display
+                let window = focus::get_focused_window().ok().map(|w| w.window_id);
+                out.extend(ticker.lock().unwrap().focus_changed(window, now));
+            }
+
+            for output in out {
+                emit_tick(output);
+            }
+        }
+    });
+
+    capture::listen(kind, grab, move |event| {
+        let (out, forward) = {
+            let mut listener = listener.lock().unwrap();
+            (listener.process(event), listener.take_forwarded())
+        };
+        for output in out {
+            emit(output);
+        }
+        forward
+    })
+}
+
+// Lets the `listen` process answer queries written to its stdin:
+// `key-state` prints the held keys, `reset` releases all of them.
+pub fn spawn_stdin_commands<F>(listener: Arc<Mutex<Listener>>, emit: F)
+where
+    F: Fn(Output) + Send + 'static,
+{
+    std::thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            let out = match line.trim() {
+                "key-state" => vec![Output::KeyState(listener.lock().unwrap().snapshot())],
+                "reset" => listener.lock().unwrap().reset(SystemTime::now()),
+                "" => continue,
+                other => {
+                    eprintln!("Unknown listener command: {}", other);
+                    continue;
+                }
+            };
+            for output in out {
+                emit(output);
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    fn moved(listener: &mut Listener, ms: u64, x: f64) -> Vec<f64> {
+        let out = listener.process(Event {
+            time: at(ms),
+            name: None,
+            event_type: EventType::MouseMove { x, y: 0.0 },
+        });
+        xs(out)
+    }
+
+    fn xs(out: Vec<Output>) -> Vec<f64> {
+        out.into_iter()
+            .filter_map(|output| match output {
+                Output::Event(value) => value["x"].as_f64(),
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn throttles_mouse_moves_but_reports_the_last() {
+        let mut listener = Listener {
+            format: Some(Format::V2),
+            ..Listener::default()
+        };
+        assert!(moved(&mut listener, 0, 1.0).is_empty());
+
+        listener.mouse = Some(Duration::from_millis(50));
+        assert_eq!(moved(&mut listener, 0, 1.0), vec![1.0]);
+        assert!(moved(&mut listener, 10, 2.0).is_empty());
+        assert!(moved(&mut listener, 20, 3.0).is_empty());
+        assert!(xs(listener.tick(at(40))).is_empty());
+        assert_eq!(xs(listener.tick(at(50))), vec![3.0]);
+        assert_eq!(moved(&mut listener, 120, 4.0), vec![4.0]);
+        assert!(xs(listener.tick(at(200))).is_empty());
+    }
+}
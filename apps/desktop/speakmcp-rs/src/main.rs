@@ -1,90 +1,156 @@
-use rdev::{listen, Event, EventType};
-use serde::Serialize;
-use serde_json::json;
-
-#[derive(Serialize)]
-struct RdevEvent {
-    event_type: String,
-    name: Option<String>,
-    time: std::time::SystemTime,
-    data: String,
-}
+mod audio;
+mod backend;
+mod capture;
+mod daemon;
+mod dictate;
+mod event;
+mod focus;
+mod grab;
+mod history;
+mod hotkey;
+mod injection;
+mod input;
+mod keyseq;
+mod keystate;
+mod layout;
+mod listener;
+mod paste;
+mod replay;
+mod session;
+mod writer;
 
+use std::fs::File;
+use std::io::{LineWriter, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
 
+use event::Format;
+use hotkey::{Binding, HotkeyEngine};
+use keystate::KeyState;
+use listener::{Listener, Output};
+use writer::{write_text, Strategy, TypingOptions};
 
-fn deal_event_to_json(event: Event) -> RdevEvent {
-    let mut jsonify_event = RdevEvent {
-        event_type: "".to_string(),
-        name: event.name,
-        time: event.time,
-        data: "".to_string(),
-    };
-    match event.event_type {
-        EventType::KeyPress(key) => {
-            jsonify_event.event_type = "KeyPress".to_string();
-            jsonify_event.data = json!({
-                "key": format!("{:?}", key)
-            })
-            .to_string();
-        }
-        EventType::KeyRelease(key) => {
-            jsonify_event.event_type = "KeyRelease".to_string();
-            jsonify_event.data = json!({
-                "key": format!("{:?}", key)
-            })
-            .to_string();
-        }
-        EventType::MouseMove { x, y } => {
-            jsonify_event.event_type = "MouseMove".to_string();
-            jsonify_event.data = json!({
-                "x": x,
-                "y": y
-            })
-            .to_string();
-        }
-        EventType::ButtonPress(key) => {
-            jsonify_event.event_type = "ButtonPress".to_string();
-            jsonify_event.data = json!({
-                "key": format!("{:?}", key)
-            })
-            .to_string();
+fn flag_value<'a>(args: &'a [String], flag: &str) -> Option<&'a str> {
+    args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1))
+        .map(String::as_str)
+}
+
+// First argument from `start` on that is neither a flag nor a flag's value
+fn positional<'a>(args: &'a [String], start: usize, flags_with_values: &[&str]) -> Option<&'a str> {
+    let mut i = start;
+    while i < args.len() {
+        if flags_with_values.contains(&args[i].as_str()) {
+            i += 2;
+        } else if args[i].starts_with("--") {
+            i += 1;
+        } else {
+            return Some(&args[i]);
         }
-        EventType::ButtonRelease(key) => {
-            jsonify_event.event_type = "ButtonRelease".to_string();
-            jsonify_event.data = json!({
-                "key": format!("{:?}", key)
-            })
-            .to_string();
+    }
+    None
+}
+
+fn parse_flag<T: std::str::FromStr>(args: &[String], flag: &str) -> Option<T>
+where
+    T::Err: std::fmt::Display,
+{
+    let value = flag_value(args, flag)?;
+    match value.parse() {
+        Ok(value) => Some(value),
+        Err(e) => {
+            eprintln!("Invalid value for {}: {}", flag, e);
+            std::process::exit(1);
         }
-        EventType::Wheel { delta_x, delta_y } => {
-            jsonify_event.event_type = "Wheel".to_string();
-            jsonify_event.data = json!({
-                "delta_x": delta_x,
-                "delta_y": delta_y
+    }
+}
+
+// Flags of `write` that take a value, shared with `dictate`
+const WRITE_FLAGS: &[&str] = &[
+    "--strategy",
+    "--file",
+    "--cps",
+    "--chunk-size",
+    "--chunk-delay-ms",
+    "--retries",
+    "--backend",
+];
+
+fn typing_options(args: &[String]) -> TypingOptions {
+    TypingOptions {
+        chars_per_second: parse_flag(args, "--cps").map(|cps| {
+            writer::check_rate(cps).unwrap_or_else(|e| {
+                eprintln!("Invalid value for --cps: {}", e);
+                std::process::exit(1);
             })
-            .to_string();
+        }),
+        chunk_size: parse_flag(args, "--chunk-size"),
+        chunk_delay_ms: writer::check_chunk_delay(parse_flag(args, "--chunk-delay-ms").unwrap_or(0))
+            .unwrap_or_else(|e| {
+                eprintln!("Invalid value for --chunk-delay-ms: {}", e);
+                std::process::exit(1);
+            }),
+        retries: parse_flag(args, "--retries").unwrap_or(0),
+    }
+}
+
+// Text from --stdin, --file or the first positional argument, exiting with
+// the input error's code when it can't be read.
+fn read_text_argument(args: &[String], command: &str, flags_with_values: &[&str]) -> String {
+    let text = if args.iter().any(|arg| arg == "--stdin") {
+        input::read_stdin()
+    } else if let Some(path) = flag_value(args, "--file") {
+        input::read_file(path)
+    } else if let Some(text) = positional(args, 2, flags_with_values) {
+        Ok(text.to_string())
+    } else {
+        eprintln!("Missing text for {}", command.to_lowercase());
+        std::process::exit(1);
+    };
+    match text {
+        Ok(text) => text,
+        Err(e) => {
+            eprintln!("{} command failed: {}", command, e);
+            std::process::exit(e.exit_code());
         }
     }
+}
 
-    jsonify_event
+fn output_line(output: Output) -> String {
+    match output {
+        Output::Event(event) => event.to_string(),
+        Output::Hotkey(event) => serde_json::to_string(&event).unwrap(),
+        Output::KeyState(snapshot) => {
+            let mut value = serde_json::to_value(snapshot).unwrap();
+            value["event_type"] = "KeyState".into();
+            value.to_string()
+        }
+    }
 }
 
-fn write_text(text: &str) -> Result<(), Box<dyn std::error::Error>> {
-    use enigo::{Enigo, Keyboard, Settings};
+fn print_output(output: Output) {
+    println!("{}", output_line(output));
+}
 
-    let mut enigo = match Enigo::new(&Settings::default()) {
-        Ok(enigo) => enigo,
-        Err(e) => {
-            eprintln!("Failed to create Enigo instance: {}", e);
-            return Err(Box::new(e));
-        }
+// Shortcuts given with --hotkeys, exiting when they don't parse
+fn hotkeys_argument(args: &[String]) -> HotkeyEngine {
+    let bindings: Vec<Binding> = match flag_value(args, "--hotkeys") {
+        Some(hotkeys) => match serde_json::from_str(hotkeys) {
+            Ok(bindings) => bindings,
+            Err(e) => {
+                eprintln!("Invalid hotkeys: {}", e);
+                std::process::exit(1);
+            }
+        },
+        None => Vec::new(),
     };
-
-    match enigo.text(text) {
-        Ok(_) => Ok(()),
+    match HotkeyEngine::new(&bindings) {
+        Ok(hotkeys) => hotkeys,
         Err(e) => {
-            eprintln!("Failed to write text: {}", e);
-            Err(Box::new(e))
+            eprintln!("{}", e);
+            std::process::exit(1);
         }
     }
 }
@@ -93,21 +159,206 @@ fn main() {
     let args: Vec<String> = std::env::args().collect();
 
     if args.len() > 1 && args[1] == "listen" {
-        if let Err(error) = listen(move |event| match event.event_type {
-            EventType::KeyPress(_) | EventType::KeyRelease(_) => {
-                let event = deal_event_to_json(event);
-                println!("{}", serde_json::to_string(&event).unwrap());
+        let format: Format = match flag_value(&args, "--format") {
+            Some(format) => match format.parse() {
+                Ok(format) => format,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => Format::default(),
+        };
+
+        let hotkeys = hotkeys_argument(&args);
+
+        let mut keys = KeyState::default();
+        if let Some(timeout) = flag_value(&args, "--stuck-key-timeout-ms") {
+            match timeout.parse() {
+                Ok(ms) => keys.set_timeout(Duration::from_millis(ms)),
+                Err(e) => {
+                    eprintln!("Invalid stuck key timeout: {}", e);
+                    std::process::exit(1);
+                }
+            }
+        }
+
+        let mut listener = Listener::default();
+        listener.format = Some(format);
+        listener.hotkeys = hotkeys;
+        listener.private = args.iter().any(|arg| arg == "--private");
+        listener.keys = keys;
+        listener.release_on_focus_change = args.iter().any(|arg| arg == "--release-on-focus-change");
+        if args.iter().any(|arg| arg == "--mouse") {
+            let interval = parse_flag(&args, "--mouse-move-interval-ms")
+                .unwrap_or(listener::DEFAULT_MOUSE_MOVE_INTERVAL_MS);
+            listener.mouse = Some(Duration::from_millis(interval));
+        }
+        if args.iter().any(|arg| arg == "--grab") {
+            listener.grab = Some(grab::Grab::default());
+        }
+
+        // Everything printed also goes to the recording, a line at a time so
+        // an interrupted session keeps what it saw
+        let recording = flag_value(&args, "--record").map(|path| match File::create(path) {
+            Ok(file) => Arc::new(Mutex::new(LineWriter::new(file))),
+            Err(e) => {
+                eprintln!("Can't create the recording {}: {}", path, e);
+                std::process::exit(1);
+            }
+        });
+        let emit = move |output: Output| {
+            let line = output_line(output);
+            println!("{}", line);
+            if let Some(recording) = &recording {
+                if let Err(e) = writeln!(recording.lock().unwrap(), "{}", line) {
+                    eprintln!("Failed to write the recording: {}", e);
+                }
             }
+        };
 
-            _ => {}
-        }) {
-            eprintln!("!error: {:?}", error);
+        let listener = Arc::new(Mutex::new(listener));
+        listener::spawn_stdin_commands(listener.clone(), emit.clone());
+        if let Err(error) = listener::run(listener, parse_flag(&args, "--listener"), emit) {
+            eprintln!("!error: {}", error);
+            std::process::exit(1);
+        }
+    } else if args.len() > 2 && args[1] == "replay" {
+        let Some(path) = positional(&args, 2, &["--speed", "--hotkeys"]) else {
+            eprintln!("Missing recording for replay");
+            std::process::exit(1);
+        };
+        let file = match File::open(path) {
+            Ok(file) => file,
+            Err(e) => {
+                eprintln!("Can't open the recording {}: {}", path, e);
+                std::process::exit(1);
+            }
+        };
+        let speed = if args.iter().any(|arg| arg == "--instant") {
+            None
+        } else {
+            match parse_flag::<f64>(&args, "--speed").unwrap_or(1.0) {
+                speed if speed >= replay::MIN_SPEED => Some(speed),
+                _ => {
+                    eprintln!("Invalid value for --speed: must be at least {}", replay::MIN_SPEED);
+                    std::process::exit(1);
+                }
+            }
+        };
+        let options = replay::ReplayOptions {
+            speed,
+            hotkeys: flag_value(&args, "--hotkeys").map(|_| hotkeys_argument(&args)),
+        };
+        if let Err(e) = replay::replay(std::io::BufReader::new(file), options, print_output) {
+            eprintln!("Replay command failed: {}", e);
+            std::process::exit(101);
+        }
+    } else if args.len() > 1 && args[1] == "record" {
+        if args.iter().any(|arg| arg == "--list-devices") {
+            match audio::device_names() {
+                Ok(names) => {
+                    for name in names {
+                        println!("{}", name);
+                    }
+                    std::process::exit(0);
+                }
+                Err(e) => {
+                    eprintln!("Record command failed: {}", e);
+                    std::process::exit(101);
+                }
+            }
+        }
+        let flags = [
+            "--device",
+            "--input-file",
+            "--format",
+            "--duration-ms",
+            "--vad-threshold-db",
+            "--stop-after-silence-ms",
+            "--level-interval-ms",
+        ];
+        let Some(path) = positional(&args, 2, &flags) else {
+            eprintln!("Missing output file for record");
             std::process::exit(1);
+        };
+        let path = std::path::PathBuf::from(path);
+        let source = if args.iter().any(|arg| arg == "--null") {
+            audio::SourceSpec::Null
+        } else if let Some(file) = flag_value(&args, "--input-file") {
+            audio::SourceSpec::File(file.into())
+        } else {
+            audio::SourceSpec::Device(flag_value(&args, "--device").map(String::from))
+        };
+        let duration = parse_flag(&args, "--duration-ms").map(Duration::from_millis);
+        let stop_after_silence_ms = parse_flag(&args, "--stop-after-silence-ms");
+        let vad = (args.iter().any(|arg| arg == "--vad") || stop_after_silence_ms.is_some()).then(|| {
+            let defaults = audio::VadOptions::default();
+            audio::VadOptions {
+                threshold_db: parse_flag(&args, "--vad-threshold-db").unwrap_or(defaults.threshold_db),
+                stop_after_silence_ms,
+                ..defaults
+            }
+        });
+        let options = audio::RecordOptions {
+            source,
+            format: parse_flag(&args, "--format").unwrap_or_else(|| audio::AudioFormat::from_path(&path)),
+            path,
+            max_duration: duration,
+            vad,
+            levels: args.iter().any(|arg| arg == "--levels").then(|| {
+                match parse_flag(&args, "--level-interval-ms").unwrap_or(audio::DEFAULT_LEVEL_INTERVAL_MS) {
+                    interval if interval >= audio::MIN_LEVEL_INTERVAL_MS => Duration::from_millis(interval),
+                    _ => {
+                        eprintln!(
+                            "Invalid value for --level-interval-ms: must be at least {}",
+                            audio::MIN_LEVEL_INTERVAL_MS
+                        );
+                        std::process::exit(1);
+                    }
+                }
+            }),
+            realtime: true,
+        };
+
+        let on_event = |event| match event {
+            audio::AudioEvent::Speech(event) => println!("{}", serde_json::to_string(&event).unwrap()),
+            audio::AudioEvent::Level(level) => println!("{}", serde_json::to_string(&level).unwrap()),
+            audio::AudioEvent::Ended(_) => {}
+        };
+        let recording = match audio::start(options, on_event) {
+            Ok(recording) => recording,
+            Err(e) => {
+                eprintln!("Record command failed: {}", e);
+                std::process::exit(101);
+            }
+        };
+        // Without a duration, a line or the end of stdin stops the recording
+        let stdin_closed = Arc::new(AtomicBool::new(false));
+        if duration.is_none() {
+            let stdin_closed = stdin_closed.clone();
+            std::thread::spawn(move || {
+                let _ = std::io::stdin().read_line(&mut String::new());
+                stdin_closed.store(true, Ordering::SeqCst);
+            });
+        }
+        while !recording.is_finished() && !stdin_closed.load(Ordering::SeqCst) {
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        match recording.stop() {
+            Ok(summary) => println!("{}", serde_json::to_string(&summary).unwrap()),
+            Err(e) => {
+                eprintln!("Record command failed: {}", e);
+                std::process::exit(101);
+            }
         }
     } else if args.len() > 2 && args[1] == "write" {
-        let text = args[2].clone();
+        let strategy: Strategy = parse_flag(&args, "--strategy").unwrap_or_default();
+        let options = typing_options(&args);
+        let text = read_text_argument(&args, "Write", WRITE_FLAGS);
 
-        match write_text(text.as_str()) {
+        let backend = parse_flag(&args, "--backend");
+        match write_text(text.as_str(), strategy, &options, backend) {
             Ok(_) => {
                 std::process::exit(0);
             },
@@ -116,11 +367,173 @@ fn main() {
                 std::process::exit(101);
             }
         }
+    } else if args.len() > 2 && args[1] == "dictate" {
+        let strategy: Strategy = parse_flag(&args, "--strategy").unwrap_or_default();
+        let options = typing_options(&args);
+        let language = flag_value(&args, "--language").unwrap_or(dictate::DEFAULT_LANGUAGE);
+        let vocabularies = match flag_value(&args, "--commands") {
+            Some(path) => match dictate::load_vocabularies(path) {
+                Ok(vocabularies) => vocabularies,
+                Err(e) => {
+                    eprintln!("{}", e);
+                    std::process::exit(1);
+                }
+            },
+            None => dictate::Vocabularies::new(),
+        };
+        let flags = [WRITE_FLAGS, &["--language", "--commands"]].concat();
+        let text = read_text_argument(&args, "Dictate", &flags);
+
+        let commands = dictate::vocabulary(&vocabularies, language);
+        if let Err(e) = dictate::dictate_text(&text, &commands, strategy, &options, parse_flag(&args, "--backend")) {
+            eprintln!("Dictate command failed: {}", e);
+            std::process::exit(101);
+        }
+    } else if args.len() > 2 && args[1] == "keys" {
+        let sequence = if args.iter().any(|arg| arg == "--stdin") {
+            match input::read_stdin() {
+                Ok(sequence) => sequence,
+                Err(e) => {
+                    eprintln!("Keys command failed: {}", e);
+                    std::process::exit(e.exit_code());
+                }
+            }
+        } else if let Some(sequence) = positional(&args, 2, &["--backend"]) {
+            sequence.to_string()
+        } else {
+            eprintln!("Missing key sequence");
+            std::process::exit(1);
+        };
+        let actions = match keyseq::parse(&sequence) {
+            Ok(actions) => actions,
+            Err(e) => {
+                eprintln!("Invalid key sequence: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        let result = backend::open(parse_flag(&args, "--backend"))
+            .map_err(Into::into)
+            .and_then(|mut backend| keyseq::execute(backend.as_mut(), &actions));
+        if let Err(e) = result {
+            eprintln!("Keys command failed: {}", e);
+            std::process::exit(101);
+        }
+    } else if args.len() > 1 && args[1] == "serve" {
+        if let Err(e) = daemon::serve(
+            parse_flag(&args, "--backend"),
+            parse_flag(&args, "--listener"),
+            args.iter().any(|arg| arg == "--grab"),
+        ) {
+            eprintln!("Serve command failed: {}", e);
+            std::process::exit(101);
+        }
+    } else if args.len() > 1 && args[1] == "get-focus" {
+        match focus::get_focused_window() {
+            Ok(window) => {
+                println!("{}", serde_json::to_string(&window).unwrap());
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Get focus command failed: {}", e);
+                std::process::exit(101);
+            }
+        }
+    } else if args.len() > 2 && args[1] == "restore-focus" {
+        let window: focus::FocusedWindow = match serde_json::from_str(&args[2]) {
+            Ok(window) => window,
+            Err(e) => {
+                eprintln!("Invalid window descriptor: {}", e);
+                std::process::exit(1);
+            }
+        };
+
+        match focus::restore_focus(&window) {
+            Ok(_) => {
+                std::process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Restore focus command failed: {}", e);
+                std::process::exit(101);
+            }
+        }
     } else {
-        eprintln!("Usage: {} [listen|write <text>]", args.get(0).unwrap_or(&"speakmcp-rs".to_string()));
+        eprintln!("Usage: {} [listen [options]|replay [options] <file>|record [options] <file>|serve [--backend <name>] [--listener <name>] [--grab]|write [options] <text|--stdin|--file <path>>|dictate [options] <text|--stdin|--file <path>>|keys [--backend <name>] <sequence|--stdin>|get-focus|restore-focus <window>]", args.first().unwrap_or(&"speakmcp-rs".to_string()));
         eprintln!("Commands:");
-        eprintln!("  listen       - Listen for keyboard events");
-        eprintln!("  write <text> - Write text using accessibility API");
+        eprintln!("  listen                 - Listen for keyboard events");
+        eprintln!("    --format v1|v2       - Event schema, v1 (default) encodes data as a string");
+        eprintln!("                           key events carry evdev_code, keycode and the char of the layout");
+        eprintln!("                           (the X server's, or XKB_DEFAULT_LAYOUT without X11)");
+        eprintln!("    --hotkeys <json>     - Shortcuts to report as hotkey events, e.g.");
+        eprintln!("                           [{{\"id\":\"record\",\"combo\":\"ctrl\",\"mode\":\"hold\"}}]");
+        eprintln!("                           mouse buttons (mousemiddle, mouseback, mouse4, ...) and");
+        eprintln!("                           wheelup/wheeldown (toggle only) work as keys");
+        eprintln!("                           mode \"sequence\" takes comma separated steps such as");
+        eprintln!("                           \"altgr, altgr\" pressed within sequence_timeout_ms (default 500)");
+        eprintln!("                           \"match_by\":\"char\" matches the key typing the character on the");
+        eprintln!("                           active layout instead of the US position (default \"position\")");
+        eprintln!("    --private            - Only name modifiers and hotkey keys, report others as \"Other\"");
+        eprintln!("    --stuck-key-timeout-ms <ms>");
+        eprintln!("                         - Release keys held this long without repeat (default 10000)");
+        eprintln!("    --release-on-focus-change");
+        eprintln!("                         - Release held keys when the focused window changes");
+        eprintln!("    --listener rdev|evdev");
+        eprintln!("                         - Listener backend, evdev reads /dev/input and needs the");
+        eprintln!("                           input group; picked for the session by default");
+        eprintln!("    --mouse              - Also report mouse buttons, wheel and moves");
+        eprintln!("    --mouse-move-interval-ms <ms>");
+        eprintln!("                         - Report at most one move per interval (default 50)");
+        eprintln!("    --grab               - Keep hotkeys from the focused app (evdev only, needs");
+        eprintln!("                           /dev/uinput); other keys are passed on unchanged");
+        eprintln!("    --record <file>      - Also save the printed lines to a file for replay");
+        eprintln!("    stdin: key-state     - Print held keys and modifiers");
+        eprintln!("    stdin: reset         - Release all held keys");
+        eprintln!("  replay <file>          - Print a recording with its original timing");
+        eprintln!("    --speed <factor>     - Play faster (2) or slower (0.5) than recorded");
+        eprintln!("    --instant            - Print everything without waiting");
+        eprintln!("    --hotkeys <json>     - Recompute hotkey events for these shortcuts instead of");
+        eprintln!("                           printing the recorded ones");
+        eprintln!("  record <file>          - Record 16 kHz mono audio until Enter or the end of stdin,");
+        eprintln!("                           then print a summary as JSON");
+        eprintln!("    --format wav|opus    - Output format, by the file extension by default (.opus/.ogg);");
+        eprintln!("                           opus needs a build with --features opus");
+        eprintln!("    --device <name>      - Input device, the default one otherwise");
+        eprintln!("    --input-file <wav>   - Play a WAV file as the input, stops at its end");
+        eprintln!("    --null               - Record silence, for machines without a microphone");
+        eprintln!("    --duration-ms <ms>   - Stop after this long instead of on stdin");
+        eprintln!("    --vad                - Print speech_start and speech_end events as JSON lines");
+        eprintln!("    --vad-threshold-db <db>");
+        eprintln!("                         - Level speech has to exceed (default -45)");
+        eprintln!("    --stop-after-silence-ms <ms>");
+        eprintln!("                         - Stop once speech was followed by this much silence (implies --vad)");
+        eprintln!("    --levels             - Print level events with RMS, peak, dBFS and clipping");
+        eprintln!("    --level-interval-ms <ms>");
+        eprintln!("                         - Report the level once per this much audio (default 50, at least 10)");
+        eprintln!("  record --list-devices  - Print the names of the input devices");
+        eprintln!("  serve                  - Run as a JSON-RPC daemon over stdio");
+        eprintln!("  write <text>           - Write text using accessibility API");
+        eprintln!("  write --stdin          - Write UTF-8 text read from stdin");
+        eprintln!("  write --file <path>    - Write UTF-8 text read from a file");
+        eprintln!("    --strategy type|paste");
+        eprintln!("                         - Type characters (default) or paste through the clipboard");
+        eprintln!("    --cps <n>            - Maximum characters per second");
+        eprintln!("    --chunk-size <n>     - Characters typed per batch");
+        eprintln!("    --chunk-delay-ms <ms> - Pause after every batch (at most 10000)");
+        eprintln!("    --retries <n>        - Retry a failed character this many times, typing one at a time");
+        eprintln!("    --backend x11|uinput|wayland");
+        eprintln!("                         - Input backend, picked for the session by default");
+        eprintln!("  dictate <text>         - Write a transcript, turning voice commands such as");
+        eprintln!("                           \"new line\" or \"select all\" into text and keys");
+        eprintln!("    --language <code>    - Voice command language (default en)");
+        eprintln!("    --commands <file>    - JSON voice commands by language, e.g.");
+        eprintln!("                           {{\"en\":[{{\"phrase\":\"new line\",\"action\":{{\"text\":\"\\n\"}}}}]}}");
+        eprintln!("    --stdin, --file and the write options are accepted as well");
+        eprintln!("  keys <sequence>        - Send text and keys, e.g. \"hello{{Enter}}{{Ctrl+a}}{{Sleep 200}}\"");
+        eprintln!("                           {{Key}} clicks, {{Key down}}/{{Key up}} press/release,");
+        eprintln!("                           {{Key 3}} repeats, {{{{ and }}}} are literal braces");
+        eprintln!("  keys --stdin           - Send a key sequence read from stdin");
+        eprintln!("  get-focus              - Print the focused window as JSON");
+        eprintln!("  restore-focus <window> - Focus a window printed by get-focus");
         std::process::exit(1);
     }
 }
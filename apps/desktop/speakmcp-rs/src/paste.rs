@@ -0,0 +1,170 @@
+use std::time::Duration;
+
+use arboard::Clipboard;
+use enigo::{Direction, Key};
+
+use crate::backend::Backend;
+use crate::focus;
+
+// Time the target application gets to request the clipboard contents before
+// we put the previous contents back.
+const PASTE_SETTLE: Duration = Duration::from_millis(150);
+
+// Process names come from /proc/<pid>/comm, which the kernel cuts off here
+const COMM_LEN: usize = 15;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasteChord {
+    CtrlV,
+    MetaV,
+    // Terminals take Ctrl+V as a literal control character
+    CtrlShiftV,
+    // xterm and urxvt paste the clipboard nowhere else by default
+    ShiftInsert,
+}
+
+impl PasteChord {
+    fn keys(self) -> (&'static [Key], Key) {
+        match self {
+            PasteChord::CtrlV => (&[Key::Control], Key::Unicode('v')),
+            PasteChord::MetaV => (&[Key::Meta], Key::Unicode('v')),
+            PasteChord::CtrlShiftV => (&[Key::Control, Key::Shift], Key::Unicode('v')),
+            PasteChord::ShiftInsert => (&[Key::Shift], Key::Insert),
+        }
+    }
+}
+
+const TERMINALS: &[(&str, PasteChord)] = &[
+    ("alacritty", PasteChord::CtrlShiftV),
+    ("foot", PasteChord::CtrlShiftV),
+    ("gnome-terminal-server", PasteChord::CtrlShiftV),
+    ("kgx", PasteChord::CtrlShiftV),
+    ("kitty", PasteChord::CtrlShiftV),
+    ("konsole", PasteChord::CtrlShiftV),
+    ("ptyxis", PasteChord::CtrlShiftV),
+    ("terminator", PasteChord::CtrlShiftV),
+    ("tilix", PasteChord::CtrlShiftV),
+    ("urxvt", PasteChord::ShiftInsert),
+    ("wezterm-gui", PasteChord::CtrlShiftV),
+    ("xfce4-terminal", PasteChord::CtrlShiftV),
+    ("xterm", PasteChord::ShiftInsert),
+];
+
+// The chord that pastes into the terminal with this process name, if it is
+// one. Names are compared as far as comm keeps them.
+pub fn terminal_chord(process_name: &str) -> Option<PasteChord> {
+    let name: String = process_name.to_lowercase().chars().take(COMM_LEN).collect();
+    TERMINALS
+        .iter()
+        .find(|(terminal, _)| terminal.chars().take(COMM_LEN).eq(name.chars()))
+        .map(|(_, chord)| *chord)
+}
+
+fn paste_chord() -> PasteChord {
+    if cfg!(target_os = "macos") {
+        return PasteChord::MetaV;
+    }
+
+    focus::get_focused_window()
+        .ok()
+        .and_then(|window| window.process_name)
+        .and_then(|name| terminal_chord(&name))
+        .unwrap_or(PasteChord::CtrlV)
+}
+
+fn send_paste_chord(backend: &mut dyn Backend, chord: PasteChord) -> Result<(), enigo::InputError> {
+    let (modifiers, key) = chord.keys();
+    let mut pressed = Vec::new();
+    let mut result = Ok(());
+    for modifier in modifiers {
+        result = backend.key(*modifier, Direction::Press);
+        if result.is_err() {
+            break;
+        }
+        pressed.push(*modifier);
+    }
+    if result.is_ok() {
+        result = backend.key(key, Direction::Click);
+    }
+    // Always let go of the modifiers, a stuck Ctrl is worse than a failed paste
+    for modifier in pressed.into_iter().rev() {
+        let released = backend.key(modifier, Direction::Release);
+        if result.is_ok() {
+            result = released;
+        }
+    }
+    result
+}
+
+pub fn paste_text(backend: &mut dyn Backend, text: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let mut clipboard =
+        Clipboard::new().map_err(|e| format!("Failed to open clipboard: {}", e))?;
+    // Only text is preserved, other content (images, files) is lost
+    let saved = clipboard.get_text().ok();
+
+    clipboard
+        .set_text(text)
+        .map_err(|e| format!("Failed to set clipboard: {}", e))?;
+    let result = send_paste_chord(backend, paste_chord());
+    std::thread::sleep(PASTE_SETTLE);
+
+    let restored = match saved {
+        Some(saved) => clipboard.set_text(saved),
+        None => clipboard.clear(),
+    };
+    if let Err(e) = restored {
+        eprintln!("Failed to restore clipboard: {}", e);
+    }
+
+    result.map_err(|e| {
+        format!("Failed to send paste shortcut with the {} backend: {}", backend.name(), e).into()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn picks_the_paste_chord_of_each_terminal() {
+        // What /proc/<pid>/comm holds for GNOME Terminal
+        assert_eq!(terminal_chord("gnome-terminal-"), Some(PasteChord::CtrlShiftV));
+        assert_eq!(terminal_chord("gnome-terminal-server"), Some(PasteChord::CtrlShiftV));
+        assert_eq!(terminal_chord("Alacritty"), Some(PasteChord::CtrlShiftV));
+        assert_eq!(terminal_chord("xterm"), Some(PasteChord::ShiftInsert));
+        assert_eq!(terminal_chord("urxvt"), Some(PasteChord::ShiftInsert));
+        assert_eq!(terminal_chord("code"), None);
+        assert_eq!(terminal_chord("gnome-terminal"), None);
+    }
+
+    // The name focus reports for a process whose executable is named like
+    // GNOME Terminal's
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn matches_the_truncated_comm_of_a_real_process() {
+        let dir = tempfile::tempdir().unwrap();
+        let exe = dir.path().join("gnome-terminal-server");
+        std::fs::copy("/bin/sleep", &exe).unwrap();
+        let mut child = std::process::Command::new(&exe).arg("5").spawn().unwrap();
+        let name = focus::process_name(child.id()).unwrap();
+        child.kill().unwrap();
+        child.wait().unwrap();
+
+        assert_eq!(name, "gnome-terminal-");
+        assert_eq!(terminal_chord(&name), Some(PasteChord::CtrlShiftV));
+    }
+
+    // Run under Xvfb: `xvfb-run cargo test -- --ignored`
+    #[cfg(target_os = "linux")]
+    #[test]
+    #[ignore]
+    fn x11_selection_round_trips() {
+        let mut clipboard = Clipboard::new().unwrap();
+        clipboard.set_text("before").unwrap();
+        let saved = clipboard.get_text().unwrap();
+        clipboard.set_text("transcript").unwrap();
+        assert_eq!(Clipboard::new().unwrap().get_text().unwrap(), "transcript");
+        clipboard.set_text(saved).unwrap();
+        assert_eq!(Clipboard::new().unwrap().get_text().unwrap(), "before");
+    }
+}
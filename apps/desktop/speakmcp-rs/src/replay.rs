@@ -0,0 +1,175 @@
+use std::io::BufRead;
+use std::time::{Duration, Instant, SystemTime};
+
+use serde_json::Value;
+
+use crate::event::value_to_event;
+use crate::hotkey::HotkeyEngine;
+use crate::listener::{Listener, Output};
+
+// A hundred times slower than recorded
+pub const MIN_SPEED: f64 = 0.01;
+
+#[derive(Default)]
+pub struct ReplayOptions {
+    // How much faster than recorded to go, None to not wait at all
+    pub speed: Option<f64>,
+    // Recompute hotkey events with these shortcuts instead of repeating the
+    // recorded ones
+    pub hotkeys: Option<HotkeyEngine>,
+}
+
+// Sleeps until `time` comes up in the replay started at `started`, whose
+// first line was recorded at `first`. Times too far out to wait for, from
+// a damaged recording, go by without waiting.
+fn wait_for(time: SystemTime, first: SystemTime, started: Instant, speed: f64) {
+    let offset = time.duration_since(first).unwrap_or_default();
+    let Some(due) = Duration::try_from_secs_f64(offset.as_secs_f64() / speed)
+        .ok()
+        .and_then(|offset| started.checked_add(offset))
+    else {
+        return;
+    };
+    if let Some(wait) = due.checked_duration_since(Instant::now()) {
+        std::thread::sleep(wait);
+    }
+}
+
+// Re-emits a stream saved with `listen --record`, one JSON line at a time.
+// Lines that aren't JSON are skipped.
+pub fn replay<R, F>(input: R, options: ReplayOptions, mut emit: F) -> std::io::Result<()>
+where
+    R: BufRead,
+    F: FnMut(Output),
+{
+    // Raw events are emitted as recorded, the listener only adds hotkeys
+    let mut listener = options.hotkeys.map(|hotkeys| {
+        let mut listener = Listener::default();
+        listener.hotkeys = hotkeys;
+        listener
+    });
+    let started = Instant::now();
+    let mut first = None;
+
+    for (number, line) in input.lines().enumerate() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        let Ok(value) = serde_json::from_str::<Value>(&line) else {
+            eprintln!("Skipping line {}, it is not JSON", number + 1);
+            continue;
+        };
+        let time = value
+            .get("time")
+            .and_then(|time| serde_json::from_value::<SystemTime>(time.clone()).ok());
+        if let (Some(speed), Some(time)) = (options.speed, time) {
+            wait_for(time, *first.get_or_insert(time), started, speed);
+        }
+
+        let Some(listener) = &mut listener else {
+            emit(Output::Event(value));
+            continue;
+        };
+        let is_hotkey = value
+            .get("event_type")
+            .and_then(Value::as_str)
+            .is_some_and(|event_type| event_type.starts_with("Hotkey"));
+        if is_hotkey {
+            continue;
+        }
+        let event = value_to_event(&value);
+        let injected = value.get("injected") == Some(&Value::Bool(true));
+        emit(Output::Event(value));
+        if let (Some(event), false) = (event, injected) {
+            for output in listener.process(event) {
+                emit(output);
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::hotkey::{Binding, HotkeyEvent};
+
+    // `listen --format v2 --record` of Ctrl held for a second, with the
+    // hotkey line of a shortcut that was registered back then
+    const RECORDING: &str = r#"
+{"version":2,"name":null,"time":{"secs_since_epoch":100,"nanos_since_epoch":0},"event_type":"KeyPress","key":"ControlLeft"}
+{"version":2,"name":null,"time":{"secs_since_epoch":100,"nanos_since_epoch":500000000},"event_type":"KeyPress","key":"ControlLeft"}
+{"event_type":"HotkeyHoldStart","id":"old","time":{"secs_since_epoch":100,"nanos_since_epoch":800000000}}
+{"version":2,"name":null,"time":{"secs_since_epoch":101,"nanos_since_epoch":0},"event_type":"KeyPress","key":"ControlLeft"}
+{"version":2,"name":null,"time":{"secs_since_epoch":101,"nanos_since_epoch":100000000},"event_type":"KeyRelease","key":"ControlLeft"}
+"#;
+
+    fn at(ms: u64) -> SystemTime {
+        SystemTime::UNIX_EPOCH + Duration::from_millis(ms)
+    }
+
+    fn collect(options: ReplayOptions) -> Vec<Output> {
+        let mut out = Vec::new();
+        replay(RECORDING.as_bytes(), options, |output| out.push(output)).unwrap();
+        out
+    }
+
+    #[test]
+    fn repeats_recording_or_recomputes_hotkeys() {
+        let lines = collect(ReplayOptions::default());
+        assert_eq!(lines.len(), 5);
+        assert!(matches!(&lines[2], Output::Event(value) if value["id"] == "old"));
+
+        let bindings: Vec<Binding> =
+            serde_json::from_str(r#"[{"id":"record","combo":"ctrl","hold_delay_ms":300}]"#)
+                .unwrap();
+        let hotkeys: Vec<HotkeyEvent> = collect(ReplayOptions {
+            hotkeys: Some(HotkeyEngine::new(&bindings).unwrap()),
+            ..ReplayOptions::default()
+        })
+        .into_iter()
+        .filter_map(|output| match output {
+            Output::Hotkey(event) => Some(event),
+            _ => None,
+        })
+        .collect();
+        assert_eq!(
+            hotkeys,
+            vec![
+                HotkeyEvent::HoldStart {
+                    id: "record".to_string(),
+                    time: at(100_300),
+                    pressed: at(100_000),
+                },
+                HotkeyEvent::HoldEnd {
+                    id: "record".to_string(),
+                    time: at(101_100),
+                    cancelled: false,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn keeps_recorded_timing_at_the_given_speed() {
+        let started = Instant::now();
+        collect(ReplayOptions {
+            speed: Some(10.0),
+            hotkeys: None,
+        });
+        // 1.1 s recorded, replayed ten times faster
+        let elapsed = started.elapsed();
+        assert!(elapsed >= Duration::from_millis(110), "{:?}", elapsed);
+        assert!(elapsed < Duration::from_millis(600), "{:?}", elapsed);
+    }
+
+    #[test]
+    fn skips_waits_too_long_to_hold() {
+        let started = Instant::now();
+        let damaged = SystemTime::UNIX_EPOCH + Duration::from_secs(1 << 60);
+        wait_for(damaged, at(0), started, MIN_SPEED);
+        wait_for(at(1000), at(0), started, 1e-300);
+        assert!(started.elapsed() < Duration::from_millis(100));
+    }
+}
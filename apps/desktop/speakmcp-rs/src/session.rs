@@ -0,0 +1,99 @@
+use crate::focus::FocusedWindow;
+use crate::history::Insertion;
+use crate::writer::TypingOptions;
+
+// Text typed incrementally as it is generated, e.g. a streamed LLM response.
+// The session keeps track of what reached the application so an abort can
+// take it back. Editors that auto-indent or auto-close brackets insert more
+// than we typed, which the model can't see.
+pub struct TypingSession {
+    pub options: TypingOptions,
+    // Focus when the session began, None if it couldn't be determined
+    pub window: Option<FocusedWindow>,
+    typed: String,
+    // A trailing "\r" held back in case the next delta starts with "\n"
+    pending_cr: bool,
+}
+
+impl TypingSession {
+    pub fn new(options: TypingOptions, window: Option<FocusedWindow>) -> Self {
+        TypingSession {
+            options,
+            window,
+            typed: String::new(),
+            pending_cr: false,
+        }
+    }
+
+    // Text to type for `delta`, with "\r\n" and "\r" turned into "\n" so
+    // every line break is a single Enter.
+    pub fn prepare(&mut self, delta: &str) -> String {
+        let mut text = String::with_capacity(delta.len() + 1);
+        if std::mem::take(&mut self.pending_cr) {
+            text.push('\r');
+        }
+        text.push_str(delta);
+        if text.ends_with('\r') {
+            text.pop();
+            self.pending_cr = true;
+        }
+        text.replace("\r\n", "\n").replace('\r', "\n")
+    }
+
+    // Whatever is still held back once no more deltas will come
+    pub fn flush(&mut self) -> String {
+        if std::mem::take(&mut self.pending_cr) {
+            "\n".to_string()
+        } else {
+            String::new()
+        }
+    }
+
+    pub fn typed(&mut self, text: &str) {
+        self.typed.push_str(text);
+    }
+
+    pub fn chars(&self) -> usize {
+        self.typed.chars().count()
+    }
+
+    // Where focus can't be queried at all (no X11 backend) the session runs
+    // blind, otherwise it is bound to the window it began in.
+    pub fn focus_changed(&self, focused: Option<&FocusedWindow>) -> bool {
+        match (&self.window, focused) {
+            (Some(window), Some(focused)) => window.window_id != focused.window_id,
+            (Some(_), None) => true,
+            (None, _) => false,
+        }
+    }
+
+    pub fn into_insertion(self) -> Insertion {
+        Insertion {
+            chars: self.chars(),
+            window: self.window,
+            keys: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn line_endings_split_across_deltas_type_one_enter() {
+        let mut session = TypingSession::new(TypingOptions::default(), None);
+        let mut typed = String::new();
+        for delta in ["Hé", "llo\r", "\nworld\r", "x\r"] {
+            let text = session.prepare(delta);
+            session.typed(&text);
+            typed.push_str(&text);
+        }
+        let rest = session.flush();
+        session.typed(&rest);
+        typed.push_str(&rest);
+
+        assert_eq!(typed, "Héllo\nworld\nx\n");
+        assert_eq!(session.chars(), 14);
+    }
+}
@@ -0,0 +1,345 @@
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+
+use crate::backend::{self, Backend, BackendKind};
+use serde::{Deserialize, Serialize};
+
+use crate::paste::paste_text;
+
+#[derive(Deserialize, Debug, Clone, Copy, Default, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum Strategy {
+    // Type character by character through enigo
+    #[default]
+    Type,
+    // Put the text on the clipboard and send the paste shortcut
+    Paste,
+}
+
+impl FromStr for Strategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "type" => Ok(Strategy::Type),
+            "paste" => Ok(Strategy::Paste),
+            _ => Err(format!("Unknown write strategy: {} (expected type or paste)", s)),
+        }
+    }
+}
+
+const RETRY_DELAY: Duration = Duration::from_millis(50);
+// One character every ten seconds. Anything slower is a mistake, and tiny
+// rates overflow the wait before the next character.
+const MIN_CHARS_PER_SECOND: f64 = 0.1;
+
+// Rates of zero or less mean no limit, like leaving the rate out
+pub fn check_rate(cps: f64) -> Result<f64, String> {
+    if cps > 0.0 && cps < MIN_CHARS_PER_SECOND {
+        return Err(format!(
+            "chars_per_second must be at least {}, got {}",
+            MIN_CHARS_PER_SECOND, cps
+        ));
+    }
+    Ok(cps)
+}
+
+// Pauses block the daemon and cancelWrite doesn't end them, the same limit
+// as {Sleep N} in key sequences
+const MAX_CHUNK_DELAY_MS: u64 = 10_000;
+
+pub fn check_chunk_delay(ms: u64) -> Result<u64, String> {
+    if ms > MAX_CHUNK_DELAY_MS {
+        return Err(format!(
+            "chunk_delay_ms must be at most {}, got {}",
+            MAX_CHUNK_DELAY_MS, ms
+        ));
+    }
+    Ok(ms)
+}
+
+fn deserialize_chunk_delay<'de, D>(deserializer: D) -> Result<u64, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    check_chunk_delay(u64::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+fn deserialize_rate<'de, D>(deserializer: D) -> Result<Option<f64>, D::Error>
+where
+    D: serde::Deserializer<'de>,
+{
+    Option::<f64>::deserialize(deserializer)?
+        .map(check_rate)
+        .transpose()
+        .map_err(serde::de::Error::custom)
+}
+
+// Pacing for apps that drop characters when text arrives too fast (remote
+// desktops, some Electron editors, terminals). The defaults type everything
+// in one go, as before.
+#[derive(Deserialize, Debug, Clone, Default, PartialEq)]
+#[serde(default)]
+pub struct TypingOptions {
+    // Upper bound on the typing rate, None for as fast as possible
+    #[serde(deserialize_with = "deserialize_rate")]
+    pub chars_per_second: Option<f64>,
+    // Characters per enigo call, None for the whole text at once. Defaults
+    // to one when only a rate is given.
+    pub chunk_size: Option<usize>,
+    // Extra pause after every chunk
+    #[serde(deserialize_with = "deserialize_chunk_delay")]
+    pub chunk_delay_ms: u64,
+    // How often a failed character is tried again before giving up
+    pub retries: u32,
+}
+
+impl TypingOptions {
+    fn chunk_size(&self, total: usize) -> usize {
+        match (self.chunk_size, self.chars_per_second) {
+            (Some(size), _) => size.max(1),
+            (None, Some(_)) => 1,
+            (None, None) => total.max(1),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TypingReport {
+    pub typed: usize,
+    pub total: usize,
+    pub cancelled: bool,
+}
+
+pub fn chunks(text: &str, size: usize) -> Vec<&str> {
+    let mut chunks = Vec::new();
+    let mut start = 0;
+    let mut count = 0;
+    for (i, _) in text.char_indices() {
+        if count == size {
+            chunks.push(&text[start..i]);
+            start = i;
+            count = 0;
+        }
+        count += 1;
+    }
+    if start < text.len() {
+        chunks.push(&text[start..]);
+    }
+    chunks
+}
+
+// A call that failed may have typed part of its text already, so with
+// retries the chunk is typed one character per call and only the character
+// that failed is tried again.
+fn type_chunk(
+    backend: &mut dyn Backend,
+    chunk: &str,
+    retries: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    if retries == 0 {
+        return type_text(backend, chunk, 0);
+    }
+    let mut buffer = [0; 4];
+    for c in chunk.chars() {
+        type_text(backend, c.encode_utf8(&mut buffer), retries)?;
+    }
+    Ok(())
+}
+
+fn type_text(
+    backend: &mut dyn Backend,
+    text: &str,
+    retries: u32,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut attempt = 0;
+    loop {
+        match backend.text(text) {
+            Ok(_) => return Ok(()),
+            Err(e) if attempt < retries => {
+                attempt += 1;
+                eprintln!(
+                    "Failed to write text with the {} backend, retrying ({}/{}): {}",
+                    backend.name(),
+                    attempt,
+                    retries,
+                    e
+                );
+                std::thread::sleep(RETRY_DELAY);
+            }
+            Err(e) => {
+                return Err(format!(
+                    "Failed to write text with the {} backend: {}",
+                    backend.name(),
+                    e
+                )
+                .into());
+            }
+        }
+    }
+}
+
+// `progress` is called after every chunk with the characters typed so far
+// and the total; returning false stops typing.
+pub fn type_text_paced(
+    backend: &mut dyn Backend,
+    text: &str,
+    options: &TypingOptions,
+    mut progress: impl FnMut(usize, usize) -> bool,
+) -> Result<TypingReport, Box<dyn std::error::Error>> {
+    let total = text.chars().count();
+    let started = Instant::now();
+    let mut typed = 0;
+
+    for chunk in chunks(text, options.chunk_size(total)) {
+        type_chunk(backend, chunk, options.retries)?;
+        typed += chunk.chars().count();
+
+        if !progress(typed, total) {
+            return Ok(TypingReport {
+                typed,
+                total,
+                cancelled: true,
+            });
+        }
+        if typed == total {
+            break;
+        }
+
+        let mut pause = Duration::from_millis(options.chunk_delay_ms);
+        if let Some(cps) = options.chars_per_second.filter(|cps| *cps > 0.0) {
+            let due = Duration::from_secs_f64(typed as f64 / cps);
+            pause = pause.max(due.saturating_sub(started.elapsed()));
+        }
+        std::thread::sleep(pause);
+    }
+
+    Ok(TypingReport {
+        typed,
+        total,
+        cancelled: false,
+    })
+}
+
+pub fn insert_text(
+    backend: &mut dyn Backend,
+    text: &str,
+    strategy: Strategy,
+    options: &TypingOptions,
+    progress: impl FnMut(usize, usize) -> bool,
+) -> Result<TypingReport, Box<dyn std::error::Error>> {
+    match strategy {
+        Strategy::Type => type_text_paced(backend, text, options, progress),
+        Strategy::Paste => {
+            paste_text(backend, text)?;
+            let total = text.chars().count();
+            Ok(TypingReport {
+                typed: total,
+                total,
+                cancelled: false,
+            })
+        }
+    }
+}
+
+pub fn write_text(
+    text: &str,
+    strategy: Strategy,
+    options: &TypingOptions,
+    backend: Option<BackendKind>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut backend = backend::open(backend)?;
+    insert_text(backend.as_mut(), text, strategy, options, |_, _| true)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use enigo::{Direction, InputError, InputResult, Key, Keyboard};
+
+    #[test]
+    fn chunks_split_on_characters_not_bytes() {
+        assert_eq!(chunks("héllo", 2), vec!["hé", "ll", "o"]);
+        assert_eq!(chunks("abc", 5), vec!["abc"]);
+        assert!(chunks("", 3).is_empty());
+    }
+
+    #[test]
+    fn rate_alone_types_one_character_at_a_time() {
+        let options = TypingOptions {
+            chars_per_second: Some(20.0),
+            ..Default::default()
+        };
+        assert_eq!(options.chunk_size(10), 1);
+        assert_eq!(TypingOptions::default().chunk_size(10), 10);
+    }
+
+    // Types up to the first 'c' it sees, then fails once
+    struct FlakyBackend {
+        typed: String,
+        failed: bool,
+    }
+
+    impl Keyboard for FlakyBackend {
+        fn fast_text(&mut self, text: &str) -> InputResult<Option<()>> {
+            for c in text.chars() {
+                if c == 'c' && !self.failed {
+                    self.failed = true;
+                    return Err(InputError::Simulate("connection lost"));
+                }
+                self.typed.push(c);
+            }
+            Ok(Some(()))
+        }
+
+        fn key(&mut self, _: Key, _: Direction) -> InputResult<()> {
+            Ok(())
+        }
+
+        fn raw(&mut self, _: u16, _: Direction) -> InputResult<()> {
+            Ok(())
+        }
+    }
+
+    impl Backend for FlakyBackend {
+        fn name(&self) -> &'static str {
+            "flaky"
+        }
+    }
+
+    #[test]
+    fn retries_only_what_failed() {
+        let mut backend = FlakyBackend {
+            typed: String::new(),
+            failed: false,
+        };
+        let options = TypingOptions {
+            retries: 1,
+            ..Default::default()
+        };
+        let report = type_text_paced(&mut backend, "abcd", &options, |_, _| true).unwrap();
+        assert_eq!((report.typed, report.cancelled), (4, false));
+        assert_eq!(backend.typed, "abcd");
+
+        backend.failed = false;
+        backend.typed.clear();
+        let options = TypingOptions::default();
+        assert!(type_text_paced(&mut backend, "abcd", &options, |_, _| true).is_err());
+        assert_eq!(backend.typed, "ab");
+    }
+
+    #[test]
+    fn rejects_rates_too_slow_to_wait_for() {
+        let options = |json| serde_json::from_str::<TypingOptions>(json);
+        assert_eq!(options(r#"{"chars_per_second":0.5}"#).unwrap().chars_per_second, Some(0.5));
+        assert_eq!(options(r#"{"chars_per_second":0}"#).unwrap().chars_per_second, Some(0.0));
+        assert_eq!(options("{}").unwrap().chars_per_second, None);
+        let error = options(r#"{"chars_per_second":1e-300}"#).unwrap_err();
+        assert!(error.to_string().contains("at least 0.1"), "{}", error);
+
+        assert!(options(r#"{"chunk_delay_ms":10000}"#).is_ok());
+        let error = options(r#"{"chunk_delay_ms":4000000000}"#).unwrap_err();
+        assert!(error.to_string().contains("at most 10000"), "{}", error);
+    }
+}
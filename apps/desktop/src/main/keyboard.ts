@@ -46,7 +46,9 @@ type RdevEvent = {
 
 export const writeText = (text: string) => {
   return new Promise<void>((resolve, reject) => {
-    const child: ChildProcess = spawn(rdevPath, ["write", text])
+    // Pass the text through stdin so transcripts don't show up in process
+    // listings and aren't limited by the maximum argument length
+    const child: ChildProcess = spawn(rdevPath, ["write", "--stdin"])
 
     // Register process if agent mode is active
     if (state.isAgentModeActive) {
@@ -63,6 +65,11 @@ export const writeText = (text: string) => {
       reject(new Error(`Failed to spawn process: ${error.message}`))
     })
 
+    child.stdin?.on("error", (error) => {
+      reject(new Error(`Failed to pass text to process: ${error.message}`))
+    })
+    child.stdin?.end(text)
+
     child.on("close", (code) => {
       // writeText will trigger KeyPress event of the key A
       // I don't know why